@@ -0,0 +1,71 @@
+// Rough timings for the matching paths, run with `cargo bench`. Each case
+// reports the best of a few runs over the same input, so it needs no
+// benchmarking crate.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use codecrafters_grep::Regex;
+
+const RUNS: usize = 5;
+
+// The fastest of `RUNS` calls to `f`
+fn best_of(f: &mut dyn FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, f: &mut dyn FnMut()) {
+    println!("{:<40} {:>10.2?}", name, best_of(f));
+}
+
+// A large log, one line in ten of which has an error with a code in it
+fn log_lines(n: usize) -> Vec<String> {
+    (0..n)
+        .map(|i| {
+            if i % 10 == 0 {
+                format!("2024-01-{:02} ERROR code={} user=u{} failed to write block", i % 28 + 1, i, i % 97)
+            } else {
+                format!("2024-01-{:02} INFO request {} served in {}ms", i % 28 + 1, i, i % 500)
+            }
+        })
+        .collect()
+}
+
+// -c only needs to know whether a line matches; -o needs spans,
+// and --replace needs captures too
+fn count_versus_full_match(lines: &[String]) {
+    let regex = Regex::new(r"ERROR code=(\d+) user=(\w+)").unwrap();
+    report("is_match (-c)", &mut || {
+        black_box(lines.iter().filter(|l| regex.is_match(l) == Ok(true)).count());
+    });
+    report("find (-o)", &mut || {
+        black_box(lines.iter().filter_map(|l| regex.find(l).unwrap()).count());
+    });
+    report("captures (--replace)", &mut || {
+        black_box(lines.iter().filter_map(|l| regex.captures(l).unwrap()).count());
+    });
+}
+
+// Every match on each line, on a pattern with groups to fill: find_iter
+// records only spans, captures_iter every group
+fn spans_versus_captures(lines: &[String]) {
+    let regex = Regex::new(r"(\w+)\s+(\w+)").unwrap();
+    report("find_iter (spans only)", &mut || {
+        black_box(lines.iter().map(|l| regex.find_iter(l).count()).sum::<usize>());
+    });
+    report("captures_iter (all groups)", &mut || {
+        black_box(lines.iter().map(|l| regex.captures_iter(l).count()).sum::<usize>());
+    });
+}
+
+fn main() {
+    let lines = log_lines(100_000);
+    count_versus_full_match(&lines);
+    spans_versus_captures(&lines[..10_000]);
+}
@@ -0,0 +1,140 @@
+#!/usr/bin/env perl
+# Regenerate src/unicode_tables.rs from the Unicode data bundled with Perl:
+#   perl scripts/unicode_tables.pl > src/unicode_tables.rs
+use strict;
+use warnings;
+use Unicode::UCD qw(prop_invlist prop_value_aliases prop_values);
+
+# General categories: short name, long name
+my @categories = (
+    [qw(C Other)], [qw(Cc Control)], [qw(Cf Format)], [qw(Cn Unassigned)],
+    [qw(Co Private_Use)], [qw(Cs Surrogate)],
+    [qw(L Letter)], [qw(LC Cased_Letter)], [qw(Ll Lowercase_Letter)],
+    [qw(Lm Modifier_Letter)], [qw(Lo Other_Letter)], [qw(Lt Titlecase_Letter)],
+    [qw(Lu Uppercase_Letter)],
+    [qw(M Mark)], [qw(Mc Spacing_Mark)], [qw(Me Enclosing_Mark)], [qw(Mn Nonspacing_Mark)],
+    [qw(N Number)], [qw(Nd Decimal_Number)], [qw(Nl Letter_Number)], [qw(No Other_Number)],
+    [qw(P Punctuation)], [qw(Pc Connector_Punctuation)], [qw(Pd Dash_Punctuation)],
+    [qw(Pe Close_Punctuation)], [qw(Pf Final_Punctuation)], [qw(Pi Initial_Punctuation)],
+    [qw(Po Other_Punctuation)], [qw(Ps Open_Punctuation)],
+    [qw(S Symbol)], [qw(Sc Currency_Symbol)], [qw(Sk Modifier_Symbol)],
+    [qw(Sm Math_Symbol)], [qw(So Other_Symbol)],
+    [qw(Z Separator)], [qw(Zl Line_Separator)], [qw(Zp Paragraph_Separator)],
+    [qw(Zs Space_Separator)],
+);
+
+# Names are compared ignoring case, spaces, `_` and `-`, as UTS #18 suggests
+sub loose {
+    my $name = lc shift;
+    $name =~ s/[\s_-]//g;
+    return $name;
+}
+
+# The inversion list of a property as inclusive ranges of valid chars
+# (surrogates aren't chars, so they are cut out)
+sub ranges {
+    my @list = prop_invlist(shift);
+    my @ranges;
+    for (my $i = 0; $i < @list; $i += 2) {
+        my ($lo, $hi) = ($list[$i], defined $list[$i + 1] ? $list[$i + 1] - 1 : 0x10FFFF);
+        for my $part ([$lo, $hi < 0xD7FF ? $hi : 0xD7FF], [$lo > 0xE000 ? $lo : 0xE000, $hi]) {
+            push @ranges, $part if $part->[0] <= $part->[1];
+        }
+    }
+    return @ranges;
+}
+
+sub table {
+    my ($const, @ranges) = @_;
+    my @items = map { sprintf "('\\u{%X}', '\\u{%X}')", @$_ } @ranges;
+    my $out = "const $const: &[(char, char)] = &[";
+    my $line = "";
+    for my $item (@items) {
+        if (length($line) + length($item) + 2 > 96) {
+            $out .= "\n   $line";
+            $line = "";
+        }
+        $line .= " $item,";
+    }
+    $out .= "\n   $line" if $line ne "";
+    return $out . "\n];\n";
+}
+
+my $version = Unicode::UCD::UnicodeVersion();
+print "// Generated by scripts/unicode_tables.pl from Unicode $version; do not edit.\n";
+print "// Each table is a sorted list of inclusive char ranges.\n\n";
+
+# Print one table per value of `$property` and a sorted lookup `$lookup` from
+# every loose alias of the value to its table. `$values` lists the values as
+# [short name, long name] pairs; the tables are called `$prefix` plus a name.
+sub property {
+    my ($property, $prefix, $lookup, $comment, @values) = @_;
+    my %names;
+    for my $value (@values) {
+        my ($short, $long) = @$value;
+        (my $const = $prefix . uc $short) =~ s/\W/_/g;
+        print table($const, ranges("$property=$short")), "\n";
+        $names{loose($_)} //= $const for $short, $long, prop_value_aliases($property, $short);
+    }
+    print "// $comment by name (in loose form), sorted by name\n";
+    print "pub(crate) const $lookup: &[(&str, &[(char, char)])] = &[\n";
+    printf "    (\"%s\", %s),\n", $_, $names{$_} for sort keys %names;
+    print "];\n";
+}
+
+property("gc", "GC_", "GENERAL_CATEGORIES", "General categories", @categories);
+print "\n";
+# (Katakana_Or_Hiragana is a Script_Extensions value only, with no aliases here)
+property("sc", "SC_", "SCRIPTS", "Scripts",
+    grep { @$_ } map { [prop_value_aliases("sc", $_)] } prop_values("sc"));
+print "\n";
+property("blk", "BLK_", "BLOCKS", "Blocks", map { [$_, $_] } prop_values("blk"));
+print "\n";
+
+# Simple case folding: every char with other chars of the same fold, each
+# mapped to those others, so `k` lists `K` and the Kelvin sign `K`
+my %orbits;
+my $folds = Unicode::UCD::all_casefolds();
+for my $code (keys %$folds) {
+    my $simple = $folds->{$code}{simple} || next;
+    push @{ $orbits{hex $simple} }, $code;
+}
+my %partners;
+for my $target (keys %orbits) {
+    my @members = sort { $a <=> $b } $target, @{ $orbits{$target} };
+    for my $member (@members) {
+        $partners{$member} = [grep { $_ != $member } @members];
+    }
+}
+print "// Chars that are equal under Unicode simple case folding, sorted by the\n";
+print "// first char\n";
+print "pub(crate) const CASE_FOLDING: &[(char, &[char])] = &[\n";
+for my $code (sort { $a <=> $b } keys %partners) {
+    printf "    ('\\u{%X}', &[%s]),\n", $code, join(", ", map { sprintf "'\\u{%X}'", $_ } @{ $partners{$code} });
+}
+print "];\n";
+print "\n";
+
+# Grapheme_Cluster_Break values, without the ones Unicode no longer assigns
+# (E_Base, E_Modifier, ...) and Other, which is every char not in the table
+my @breaks = (
+    [qw(CN Control)], [qw(CR Cr)], [qw(EX Extend)], [qw(L L)], [qw(LF Lf)], [qw(LV Lv)],
+    [qw(LVT Lvt)], [qw(PP Prepend)], [qw(RI RegionalIndicator)], [qw(SM SpacingMark)],
+    [qw(T T)], [qw(V V)], [qw(ZWJ Zwj)],
+);
+my @break_ranges;
+for my $break (@breaks) {
+    my ($short, $variant) = @$break;
+    push @break_ranges, [@$_, $variant] for ranges("gcb=$short");
+}
+print "// The Grapheme_Cluster_Break property, for `\\X`\n";
+print "#[derive(Debug, Clone, Copy, PartialEq, Eq)]\n";
+print "pub(crate) enum GraphemeBreak {\n";
+print "    $_->[1],\n" for @breaks;
+print "    Other,\n";
+print "}\n\n";
+print "// Chars with a Grapheme_Cluster_Break other than Other, sorted by range\n";
+print "pub(crate) const GRAPHEME_BREAKS: &[(char, char, GraphemeBreak)] = &[\n";
+printf "    ('\\u{%X}', '\\u{%X}', GraphemeBreak::%s),\n", @$_ for sort { $a->[0] <=> $b->[0] } @break_ranges;
+print "];\n\n";
+print "pub(crate) ", table("EXTENDED_PICTOGRAPHIC", ranges("Extended_Pictographic"));
@@ -0,0 +1,234 @@
+use crate::matcher::{match_node, Groups, MatchOptions, MatchState};
+use crate::parser::{ClassItem, RegexNode, RepeatKind};
+
+// Look for the classic catastrophic-backtracking (ReDoS) shapes, where an
+// unbounded repeat can split the same text in exponentially many ways:
+//   - a repeat nested directly inside another, as in `(a+)+` or `(a*)*`
+//   - a repeated alternation whose branches can start with the same char, as in `(a|a)*`
+// Returns a description of the first one found. This is a structural check on
+// the AST, so it errs on the side of warning. Only the backtracking matcher is
+// at risk; `Regex` asks only about patterns the NFA can't run.
+pub fn find_catastrophic(node: &RegexNode) -> Option<String> {
+    match node {
+        RegexNode::Repeat { node: inner, kind, .. } => {
+            if is_unbounded(*kind) {
+                if has_nested_repeat(inner) {
+                    return Some(
+                        "a repeated group contains another unbounded repeat (like `(a+)+`), which can take exponential time to backtrack through on some inputs"
+                            .to_string(),
+                    );
+                }
+                if has_overlapping_branches(inner) {
+                    return Some(
+                        "a repeated alternation has branches that can match the same text (like `(a|a)*`), which can take exponential time to backtrack through on some inputs"
+                            .to_string(),
+                    );
+                }
+            }
+            find_catastrophic(inner)
+        }
+        RegexNode::Seq(nodes) | RegexNode::Alt(nodes) => nodes.iter().find_map(find_catastrophic),
+        RegexNode::Group { node: inner, .. }
+        | RegexNode::Lookahead { node: inner, .. }
+        | RegexNode::Lookbehind { node: inner, .. }
+        | RegexNode::Atomic(inner)
+        | RegexNode::Scoped { node: inner, .. } => find_catastrophic(inner),
+        _ => None,
+    }
+}
+
+fn is_unbounded(kind: RepeatKind) -> bool {
+    matches!(
+        kind,
+        RepeatKind::OneOrMore | RepeatKind::ZeroOrMore | RepeatKind::Bounded { max: None, .. }
+    )
+}
+
+// Can the body of a repeat match through an inner unbounded repeat of non-empty
+// text, with everything around it able to match nothing? (`(a+)+`, `(x?a*)*`;
+// not `(ab+)+`, where the `a` pins down where each iteration starts.)
+fn has_nested_repeat(node: &RegexNode) -> bool {
+    match node {
+        RegexNode::Repeat { node: inner, kind, .. } => {
+            (is_unbounded(*kind) && !nullable(inner)) || has_nested_repeat(inner)
+        }
+        RegexNode::Group { node: inner, .. } | RegexNode::Atomic(inner) | RegexNode::Scoped { node: inner, .. } => {
+            has_nested_repeat(inner)
+        }
+        RegexNode::Alt(branches) => branches.iter().any(has_nested_repeat),
+        RegexNode::Seq(nodes) => nodes.iter().enumerate().any(|(i, n)| {
+            has_nested_repeat(n)
+                && nodes.iter().enumerate().all(|(j, other)| j == i || nullable(other))
+        }),
+        _ => false,
+    }
+}
+
+// Is the body an alternation (possibly inside groups) with two branches that can
+// begin with the same char?
+fn has_overlapping_branches(node: &RegexNode) -> bool {
+    match node {
+        RegexNode::Group { node: inner, .. } | RegexNode::Atomic(inner) | RegexNode::Scoped { node: inner, .. } => {
+            has_overlapping_branches(inner)
+        }
+        RegexNode::Alt(branches) => {
+            let firsts: Vec<Vec<&RegexNode>> = branches.iter().map(first_atoms).collect();
+            firsts.iter().enumerate().any(|(i, a)| {
+                firsts[i + 1..]
+                    .iter()
+                    .any(|b| a.iter().any(|x| b.iter().any(|y| atoms_overlap(x, y))))
+            })
+        }
+        _ => false,
+    }
+}
+
+// Can the node match the empty string?
+fn nullable(node: &RegexNode) -> bool {
+    match node {
+        RegexNode::Seq(nodes) => nodes.iter().all(nullable),
+        RegexNode::Alt(branches) => branches.iter().any(nullable),
+        RegexNode::Repeat { node: inner, kind, .. } => match kind {
+            RepeatKind::ZeroOrOne | RepeatKind::ZeroOrMore => true,
+            RepeatKind::OneOrMore => nullable(inner),
+            RepeatKind::Bounded { min, .. } => *min == 0 || nullable(inner),
+        },
+        RegexNode::Group { node: inner, .. } | RegexNode::Atomic(inner) | RegexNode::Scoped { node: inner, .. } => {
+            nullable(inner)
+        }
+        RegexNode::StartAnchor
+        | RegexNode::EndAnchor
+        | RegexNode::InputStart
+        | RegexNode::InputEnd { .. }
+        | RegexNode::WordStart
+        | RegexNode::WordEnd
+        | RegexNode::WordBoundary { .. }
+        | RegexNode::Lookahead { .. }
+        | RegexNode::Lookbehind { .. } => true,
+        // A backreference to an empty group matches nothing
+        RegexNode::Backreference(_) => true,
+        _ => false,
+    }
+}
+
+// The single-char atoms a match of `node` can start with
+fn first_atoms(node: &RegexNode) -> Vec<&RegexNode> {
+    match node {
+        RegexNode::Seq(nodes) => {
+            let mut atoms = Vec::new();
+            for n in nodes {
+                atoms.extend(first_atoms(n));
+                if !nullable(n) {
+                    break;
+                }
+            }
+            atoms
+        }
+        RegexNode::Alt(branches) => branches.iter().flat_map(first_atoms).collect(),
+        RegexNode::Repeat { node: inner, .. }
+        | RegexNode::Group { node: inner, .. }
+        | RegexNode::Atomic(inner)
+        | RegexNode::Scoped { node: inner, .. } => first_atoms(inner),
+        RegexNode::StartAnchor
+        | RegexNode::EndAnchor
+        | RegexNode::InputStart
+        | RegexNode::InputEnd { .. }
+        | RegexNode::WordStart
+        | RegexNode::WordEnd
+        | RegexNode::WordBoundary { .. }
+        | RegexNode::Lookahead { .. }
+        | RegexNode::Lookbehind { .. } => vec![],
+        // The group's text (or the grapheme) could start with anything
+        RegexNode::Backreference(_) | RegexNode::Grapheme => vec![&RegexNode::Dot],
+        atom => vec![atom],
+    }
+}
+
+// Could two single-char atoms match the same char? Unless both are plain
+// literals or both are non-negated classes, assume they can.
+fn atoms_overlap(a: &RegexNode, b: &RegexNode) -> bool {
+    match (a, b) {
+        (RegexNode::Literal(x), RegexNode::Literal(y)) => x == y,
+        (RegexNode::Literal(c), class) | (class, RegexNode::Literal(c)) => atom_matches(class, *c),
+        (
+            RegexNode::CharClass { items: x, negated: false },
+            RegexNode::CharClass { items: y, negated: false },
+        ) => x.iter().any(|a| y.iter().any(|b| items_overlap(a, b))),
+        _ => true,
+    }
+}
+
+// Do two class members share a char? Sets like `\d` are assumed to.
+fn items_overlap(a: &ClassItem, b: &ClassItem) -> bool {
+    let bounds = |item: &ClassItem| match *item {
+        ClassItem::Single(c) => Some((c, c)),
+        ClassItem::Range(lo, hi) => Some((lo, hi)),
+        _ => None,
+    };
+    match (bounds(a), bounds(b)) {
+        (Some((a_lo, a_hi)), Some((b_lo, b_hi))) => a_lo <= b_hi && b_lo <= a_hi,
+        _ => true,
+    }
+}
+
+// Does a single-char atom match `c`? Asks the matcher so the answer can't drift.
+fn atom_matches(atom: &RegexNode, c: char) -> bool {
+    let input = [c];
+    let mut state = MatchState::new(atom, MatchOptions::default());
+    !match_node(atom, &input, 0, &Groups::new(), &mut state).is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn warns(pattern: &str) -> bool {
+        find_catastrophic(&Parser::new(pattern).parse().unwrap()).is_some()
+    }
+
+    #[test]
+    fn nested_repeats_are_flagged() {
+        for pattern in ["(a+)+", "(a*)*b", "(x?a*)*", "(a+)+$", "((a+))+", "(?:a+){2,}", "x(a+b?)+y", "(?i:a+)*"] {
+            assert!(warns(pattern), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn overlapping_alternations_are_flagged() {
+        for pattern in ["(a|a)*", "(a|ab)*", "(\\w|\\d)+", "([a-c]|b)+", "(?:x|\\w)*y"] {
+            assert!(warns(pattern), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn safe_shapes_are_not_flagged() {
+        for pattern in ["a+b+", "(ab)+", "(ab+)+", "(a|b)*", "(a+){3}", "(a+)?", "\\d+\\.\\d+", "(?:cat|dog)+", "[ab]*a", ""] {
+            assert!(!warns(pattern), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn regex_reports_the_warning_and_strict_rejects_it() {
+        use crate::Regex;
+        let regex = Regex::new("(a+)+b\\1").unwrap();
+        assert!(regex.warning().unwrap().contains("`(a+)+`"));
+        assert_eq!(regex.is_match("aabaa"), Ok(true));
+        assert_eq!(Regex::new_strict("(a+)+b\\1").err(), regex.warning().map(String::from));
+        assert!(Regex::new("(a+)b\\1").unwrap().warning().is_none());
+        assert!(Regex::new_strict("a+b").is_ok());
+        assert!(Regex::new_strict("(?>x)(a|ab)*").unwrap_err().contains("`(a|a)*`"));
+    }
+
+    #[test]
+    fn patterns_the_nfa_runs_are_not_flagged() {
+        use crate::Regex;
+        for pattern in ["(a+)+b", "(a|ab)*", "(a*)*$"] {
+            assert!(warns(pattern), "{}", pattern);
+            assert!(Regex::new(pattern).unwrap().warning().is_none(), "{}", pattern);
+            assert!(Regex::new_strict(pattern).is_ok(), "{}", pattern);
+        }
+        let regex = Regex::new_any(&["(a)\\1", "(b+)+c"]).unwrap();
+        assert!(regex.warning().is_some());
+    }
+}
@@ -0,0 +1,184 @@
+use std::io::{Cursor, Read, Seek};
+
+use zip::ZipArchive;
+
+// Does this (file or member) name look like a zip archive?
+pub fn is_zip(name: &str) -> bool {
+    name.to_ascii_lowercase().ends_with(".zip")
+}
+
+// How many archives deep nesting is followed, and the most a member may
+// decompress to. A zip bomb can't use up memory or time past these.
+const MAX_DEPTH: usize = 8;
+const MAX_MEMBER_SIZE: u64 = 256 << 20;
+
+// Walk every member of a zip archive without extracting it to disk, handing each
+// file member's raw bytes to `search` as a virtual file labelled `archive.zip!member`.
+// Nested archives are read into memory and walked the same way. Corrupt archives
+// or members, and those past the depth or size limit, are reported and skipped so
+// the rest of the search carries on. Returns whether `search` reported a match for
+// any member.
+pub fn search_zip<R: Read + Seek>(
+    label: &str,
+    reader: R,
+    search: &mut dyn FnMut(&str, &mut dyn Read) -> bool,
+) -> bool {
+    search_within(label, reader, MAX_DEPTH, MAX_MEMBER_SIZE, search)
+}
+
+// `search_zip`, following at most `depth` more levels of nested archives and
+// skipping members that decompress to more than `max_size` bytes
+fn search_within<R: Read + Seek>(
+    label: &str,
+    reader: R,
+    depth: usize,
+    max_size: u64,
+    search: &mut dyn FnMut(&str, &mut dyn Read) -> bool,
+) -> bool {
+    let mut archive = match ZipArchive::new(reader) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("Error reading archive {}: {}", label, e);
+            return false;
+        }
+    };
+
+    let mut any_match = false;
+    for i in 0..archive.len() {
+        let member = match archive.by_index(i) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Error reading member {} of archive {}: {}", i, label, e);
+                continue;
+            }
+        };
+        if member.is_dir() {
+            continue;
+        }
+
+        let member_label = format!("{}!{}", label, member.name());
+        if member.size() > max_size {
+            eprintln!("Skipping {}: larger than {} bytes", member_label, max_size);
+            continue;
+        }
+        // The header's size can be a lie, so no more than the limit is read either
+        if is_zip(&member_label) {
+            if depth == 0 {
+                eprintln!("Skipping archive {}: nested too deeply", member_label);
+                continue;
+            }
+            // A zip needs to seek, which a compressed member stream can't do
+            let mut bytes = Vec::new();
+            if let Err(e) = member.take(max_size + 1).read_to_end(&mut bytes) {
+                eprintln!("Error reading archive {}: {}", member_label, e);
+                continue;
+            }
+            if bytes.len() as u64 > max_size {
+                eprintln!("Skipping {}: larger than {} bytes", member_label, max_size);
+                continue;
+            }
+            any_match |= search_within(&member_label, Cursor::new(bytes), depth - 1, max_size, search);
+        } else {
+            any_match |= search(&member_label, &mut member.take(max_size));
+        }
+    }
+    any_match
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    // A zip archive holding `members`, as (name, contents) pairs
+    fn zip(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in members {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    // Search `archive`, returning each member's label and text, and whether
+    // any member contained `needle`
+    fn walk(archive: Vec<u8>, needle: &str) -> (Vec<(String, String)>, bool) {
+        walk_within(archive, needle, MAX_DEPTH, MAX_MEMBER_SIZE)
+    }
+
+    // Like `walk`, with the given depth and size limits
+    fn walk_within(archive: Vec<u8>, needle: &str, depth: usize, max_size: u64) -> (Vec<(String, String)>, bool) {
+        let mut seen = Vec::new();
+        let found = search_within("a.zip", Cursor::new(archive), depth, max_size, &mut |label, member| {
+            let mut text = String::new();
+            member.read_to_string(&mut text).unwrap();
+            let found = text.contains(needle);
+            seen.push((label.to_string(), text));
+            found
+        });
+        (seen, found)
+    }
+
+    fn members(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(l, t)| (l.to_string(), t.to_string())).collect()
+    }
+
+    #[test]
+    fn members_are_labelled_with_their_archive() {
+        let archive = zip(&[("one.txt", b"apple\n"), ("dir/two.txt", b"pear\n")]);
+        let (seen, found) = walk(archive.clone(), "pear");
+        assert_eq!(seen, members(&[("a.zip!one.txt", "apple\n"), ("a.zip!dir/two.txt", "pear\n")]));
+        assert!(found);
+        assert!(!walk(archive, "plum").1);
+    }
+
+    #[test]
+    fn nested_archives_are_walked_too() {
+        let inner = zip(&[("deep.txt", b"plum\n")]);
+        let archive = zip(&[("top.txt", b"apple\n"), ("inner.ZIP", &inner)]);
+        let (seen, found) = walk(archive, "plum");
+        assert_eq!(seen, members(&[("a.zip!top.txt", "apple\n"), ("a.zip!inner.ZIP!deep.txt", "plum\n")]));
+        assert!(found);
+    }
+
+    #[test]
+    fn corrupt_archives_are_skipped() {
+        let (seen, found) = walk(b"PK\x03\x04 not really a zip".to_vec(), "");
+        assert!(seen.is_empty() && !found);
+        // A corrupt archive inside a good one doesn't stop the rest
+        let archive = zip(&[("bad.zip", b"garbage"), ("good.txt", b"apple\n")]);
+        let (seen, found) = walk(archive, "apple");
+        assert_eq!(seen, members(&[("a.zip!good.txt", "apple\n")]));
+        assert!(found);
+    }
+
+    #[test]
+    fn archives_nested_too_deeply_are_skipped() {
+        // a.zip!1.zip!2.zip!3.zip, with a text member at every level
+        let mut archive = zip(&[("3.txt", b"plum\n")]);
+        for level in (0..3).rev() {
+            let text = format!("{}.txt", level);
+            let inner = format!("{}.zip", level + 1);
+            archive = zip(&[(&text, b"apple\n"), (&inner, &archive)]);
+        }
+        let (seen, found) = walk_within(archive.clone(), "plum", 2, MAX_MEMBER_SIZE);
+        let labels: Vec<&str> = seen.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(labels, ["a.zip!0.txt", "a.zip!1.zip!1.txt", "a.zip!1.zip!2.zip!2.txt"]);
+        assert!(!found);
+        assert!(walk_within(archive, "plum", 3, MAX_MEMBER_SIZE).1);
+    }
+
+    #[test]
+    fn members_over_the_size_limit_are_skipped() {
+        let inner = zip(&[("deep.txt", b"fig\n")]);
+        let archive = zip(&[("big.txt", b"apple\n"), ("small.txt", b"fig\n"), ("inner.zip", &inner)]);
+        let (seen, _) = walk_within(archive.clone(), "fig", MAX_DEPTH, 4);
+        assert_eq!(seen, members(&[("a.zip!small.txt", "fig\n")]));
+        let (seen, _) = walk_within(archive, "fig", MAX_DEPTH, inner.len() as u64);
+        assert_eq!(seen.len(), 3);
+    }
+}
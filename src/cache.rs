@@ -0,0 +1,106 @@
+use std::sync::{Arc, Mutex};
+
+use crate::matcher::Compiled;
+use crate::parser::Parser;
+
+const DEFAULT_CAPACITY: usize = 64;
+
+// Least-recently-used cache of parsed (and NFA-compiled) patterns, most recently used last
+struct PatternCache {
+    capacity: usize,
+    entries: Vec<(String, Arc<Compiled>)>,
+}
+
+impl PatternCache {
+    // The cached parse of `pattern`, if any, which becomes the most recently used
+    fn get(&mut self, pattern: &str) -> Option<Arc<Compiled>> {
+        let i = self.entries.iter().position(|(p, _)| p == pattern)?;
+        let entry = self.entries.remove(i);
+        let ast = Arc::clone(&entry.1);
+        self.entries.push(entry);
+        Some(ast)
+    }
+
+    // Cache `ast` as the parse of `pattern`, unless another thread got there
+    // first while this one was parsing. Either way, return the cached parse,
+    // so every caller shares one.
+    fn insert(&mut self, pattern: &str, ast: Arc<Compiled>) -> Arc<Compiled> {
+        if let Some(cached) = self.get(pattern) {
+            return cached;
+        }
+        if self.capacity > 0 {
+            if self.entries.len() >= self.capacity {
+                self.entries.remove(0);
+            }
+            self.entries.push((pattern.to_string(), Arc::clone(&ast)));
+        }
+        ast
+    }
+}
+
+static CACHE: Mutex<PatternCache> = Mutex::new(PatternCache {
+    capacity: DEFAULT_CAPACITY,
+    entries: Vec::new(),
+});
+
+// Change how many parsed patterns are kept; 0 disables caching.
+// Shrinking evicts the least recently used entries straight away.
+pub fn set_pattern_cache_capacity(capacity: usize) {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.capacity = capacity;
+    let excess = cache.entries.len().saturating_sub(capacity);
+    cache.entries.drain(..excess);
+}
+
+// Parse and compile `pattern`, reusing the result of an earlier call when
+// possible. Only successful parses are cached.
+pub fn parse_cached(pattern: &str) -> Result<Arc<Compiled>, String> {
+    if let Some(ast) = CACHE.lock().unwrap_or_else(|e| e.into_inner()).get(pattern) {
+        return Ok(ast);
+    }
+
+    // Parse outside the lock so slow patterns don't block other threads
+    let ast = Arc::new(Compiled::new(Parser::new(pattern).parse()?));
+    Ok(CACHE.lock().unwrap_or_else(|e| e.into_inner()).insert(pattern, ast))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One test, since the cache is shared by the whole process
+    #[test]
+    fn hits_reuse_the_parse_and_the_least_recent_is_evicted() {
+        set_pattern_cache_capacity(2);
+        let a = parse_cached("cache-a").unwrap();
+        assert!(Arc::ptr_eq(&a, &parse_cached("cache-a").unwrap()));
+        let b = parse_cached("cache-b").unwrap();
+        // Using `a` again leaves `b` as the one to evict when `c` comes in
+        parse_cached("cache-a").unwrap();
+        parse_cached("cache-c").unwrap();
+        assert!(Arc::ptr_eq(&a, &parse_cached("cache-a").unwrap()));
+        assert!(!Arc::ptr_eq(&b, &parse_cached("cache-b").unwrap()));
+        assert!(parse_cached("(").is_err());
+        // Shrinking evicts straight away, and 0 caches nothing
+        set_pattern_cache_capacity(1);
+        assert_eq!(CACHE.lock().unwrap().entries.len(), 1);
+        set_pattern_cache_capacity(0);
+        let d = parse_cached("cache-d").unwrap();
+        assert!(!Arc::ptr_eq(&d, &parse_cached("cache-d").unwrap()));
+        set_pattern_cache_capacity(DEFAULT_CAPACITY);
+    }
+
+    #[test]
+    fn a_pattern_cached_during_the_parse_is_not_added_twice() {
+        let mut cache = PatternCache { capacity: 2, entries: Vec::new() };
+        let parse = || Arc::new(Compiled::new(Parser::new("a+").parse().unwrap()));
+        // Two threads miss, parse, then insert one after the other
+        let first = cache.insert("a+", parse());
+        let second = cache.insert("a+", parse());
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.entries.len(), 1);
+        // With one entry for `a+`, room is left for `b` without evicting it
+        cache.insert("b", Arc::new(Compiled::new(Parser::new("b").parse().unwrap())));
+        assert!(cache.get("a+").is_some_and(|ast| Arc::ptr_eq(&ast, &first)));
+    }
+}
@@ -1,39 +1,408 @@
+use std::fs;
+
+use codecrafters_grep::MatchKind;
+
+use crate::encoding::Encoding;
+use crate::types;
+
+pub const USAGE: &str = "\
+Usage: your_program.sh -E <pattern> [options] [file...]
+       your_program.sh -E -e <pattern>... [-f <file>]... [options] [file...]
+
+Searches each file (or stdin when no file is given) for lines matching <pattern>.
+Flags may appear anywhere; the first positional argument is the pattern, unless
+patterns are given with -e or -f. Use `--` to end the flags, e.g. for a pattern
+that starts with `-`.
+
+Options:
+  -E                          Use extended regular expressions (required)
+  -e PATTERN                  Match PATTERN; repeat to match any of several patterns
+  -f FILE                     Match any of the patterns in FILE, one per line
+  -r                          Search directories recursively
+  --auto-recurse              Search directory arguments recursively even without -r
+  --hidden                    Include dotfiles and dot-directories when recursing
+  -t TYPE / -T TYPE           Only search / skip files of a built-in type
+  --type-list                 Print the built-in file types and exit
+  --search-zip                Search inside .zip archives
+  --invert-file-match PAT     Skip files (and archive members) that contain a match for PAT
+  --max-matches-total N       Stop after N matching lines (or -o matches) across all files
+  --stats                     Print how many lines each pattern matched to stderr at the end
+  --replace TEMPLATE          Print matching lines with matches replaced ($1, ${1}, ${name}, $$);
+                              with -o, print each match's expansion
+  --output-null               End each printed line with NUL instead of a newline
+  --encoding ENC              Read files as utf8 (default), utf16, utf16le or utf16be
+  -v, --invert-match          Print the lines that don't match instead
+  -n, --line-number           Prefix each line with its line number
+  -c, --count                 Print only the number of selected lines in each file
+  --count-matches             Print only the number of matches in each file, counting each
+                              empty match once
+  -q, --quiet                 Print nothing; exit 0 at the first selected line (wins over -c)
+  -o, --only-matching         Print each match on its own line instead of the whole line
+  --output-separator=STR      With -o, print a line's matches on one line, joined by STR
+  --unique[=lex|count]        With -o, print each distinct match once, sorted
+                              alphabetically (lex) or by how often it was seen (count)
+  -A N, --after-context N     Also print N lines after each selected line
+  -B N, --before-context N    Also print N lines before each selected line
+  -C N, --context N           Same as -A N -B N (an explicit -A or -B wins)
+  -i, --ignore-case           Match letters regardless of case
+  -S, --smart-case            Ignore case unless the pattern has an uppercase letter
+  --ascii                     Restrict \\w, \\s and . to ASCII
+  --leftmost-first            Report Perl-style matches (default)
+  --leftmost-longest          Report POSIX-style longest matches
+  --strict                    Reject patterns prone to catastrophic backtracking
+  --step-limit N              Give up (exit 2) once a search takes more than N steps
+  -h, --help                  Print this help and exit
+  -V, --version               Print the version and exit
+";
+
+// How --unique orders the distinct values it prints
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum UniqueOrder {
+    Lexical,
+    // Most frequent first, each with its count
+    Count,
+}
+
+#[derive(Default)]
 pub struct Arguments {
     pub recursive: bool,
-    pub pattern: String,
+    pub auto_recurse: bool,
+    pub search_zip: bool,
+    pub hidden: bool,
+    pub output_null: bool,
+    pub encoding: Encoding,
+    pub ascii: bool,
+    pub invert: bool,
+    pub line_numbers: bool,
+    pub count: bool,
+    // --count-matches: count every match, not just the lines with one
+    pub count_matches: bool,
+    // -q: print nothing and stop at the first selected line
+    pub quiet: bool,
+    pub only_matching: bool,
+    // --output-separator: join each line's -o matches with this instead of
+    // printing them on lines of their own
+    pub output_separator: Option<String>,
+    pub unique: Option<UniqueOrder>,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub case_insensitive: bool,
+    pub smart_case: bool,
+    pub strict: bool,
+    pub match_kind: MatchKind,
+    // Every pattern to match, from -e/-f or else the first positional argument
+    pub patterns: Vec<String>,
+    pub invert_file_match: Option<String>,
+    pub max_matches_total: Option<usize>,
+    pub step_limit: Option<usize>,
+    // --stats: count the lines each -e/-f pattern matched
+    pub stats: bool,
+    pub replace: Option<String>,
+    pub type_list: bool,
+    pub help: bool,
+    pub version: bool,
+    pub types: Vec<String>,
+    pub types_not: Vec<String>,
     pub files: Vec<String>,
 }
 
 impl Arguments {
     pub fn parse(args: &[String]) -> Result<Arguments, String> {
         let mut recursive = false;
+        let mut auto_recurse = false;
+        let mut search_zip = false;
+        let mut hidden = false;
+        let mut output_null = false;
+        let mut encoding = Encoding::default();
+        let mut ascii = false;
+        let mut invert = false;
+        let mut line_numbers = false;
+        let mut count = false;
+        let mut count_matches = false;
+        let mut quiet = false;
+        let mut only_matching = false;
+        let mut output_separator = None;
+        let mut unique = None;
+        let mut before_context = None;
+        let mut after_context = None;
+        let mut context = None;
+        let mut case_insensitive = false;
+        let mut smart_case = false;
+        let mut strict = false;
+        let mut match_kind = MatchKind::default();
         let mut use_extended = false;
-        let mut pattern = None;
-        let mut files = Vec::new();
-        
+        let mut patterns = Vec::new();
+        let mut positional = Vec::new();
+        let mut invert_file_match = None;
+        let mut max_matches_total = None;
+        let mut step_limit = None;
+        let mut stats = false;
+        let mut replace = None;
+        let mut types = Vec::new();
+        let mut types_not = Vec::new();
+        let mut patterns_from_flags = false;
+        let mut options_done = false;
+
         let mut i = 1;
         while i < args.len() {
-            match args[i].as_str() {
+            let arg = args[i].as_str();
+            // After `--`, and for a lone `-`, everything is the pattern or a file
+            if options_done || arg == "-" || !arg.starts_with('-') {
+                positional.push(arg.to_string());
+                i += 1;
+                continue;
+            }
+            // `-A2` is short for `-A 2`, and likewise for -B and -C
+            let (arg, attached) = match arg.get(..2) {
+                Some(flag @ ("-A" | "-B" | "-C")) if arg.len() > 2 => (flag, Some(&arg[2..])),
+                _ => (arg, None),
+            };
+            match arg {
+                "--" => options_done = true,
+                "-h" | "--help" => {
+                    return Ok(Arguments {
+                        help: true,
+                        ..Default::default()
+                    });
+                }
+                "-V" | "--version" => {
+                    return Ok(Arguments {
+                        version: true,
+                        ..Default::default()
+                    });
+                }
                 "-r" => recursive = true,
+                "--auto-recurse" => auto_recurse = true,
                 "-E" => use_extended = true,
-                _ => {
-                    if pattern.is_none() {
-                        pattern = Some(args[i].clone());
+                "--search-zip" => search_zip = true,
+                "--hidden" => hidden = true,
+                "--output-null" => output_null = true,
+                "--stats" => stats = true,
+                "--ascii" => ascii = true,
+                "-v" | "--invert-match" => invert = true,
+                "-n" | "--line-number" => line_numbers = true,
+                "-c" | "--count" => count = true,
+                "--count-matches" => count_matches = true,
+                "-q" | "--quiet" => quiet = true,
+                "-o" | "--only-matching" => only_matching = true,
+                _ if arg.starts_with("--output-separator=") => {
+                    output_separator = Some(arg["--output-separator=".len()..].to_string());
+                }
+                "--output-separator" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Expected a separator after '--output-separator'")?;
+                    output_separator = Some(value.clone());
+                }
+                "--unique" | "--unique=lex" => unique = Some(UniqueOrder::Lexical),
+                "--unique=count" => unique = Some(UniqueOrder::Count),
+                _ if arg.starts_with("--unique=") => {
+                    return Err(format!("Unknown order in '{}' (expected lex or count)", arg));
+                }
+                "-i" | "--ignore-case" => case_insensitive = true,
+                "-S" | "--smart-case" => smart_case = true,
+                "--strict" => strict = true,
+                "--leftmost-longest" => match_kind = MatchKind::LeftmostLongest,
+                "--leftmost-first" => match_kind = MatchKind::LeftmostFirst,
+                "--invert-file-match" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Expected a pattern after '--invert-file-match'")?;
+                    invert_file_match = Some(value.clone());
+                }
+                "-e" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Expected a pattern after '-e'")?;
+                    patterns.push(value.clone());
+                    patterns_from_flags = true;
+                }
+                "-f" => {
+                    i += 1;
+                    let path = args.get(i).ok_or("Expected a file after '-f'")?;
+                    let text = fs::read_to_string(path)
+                        .map_err(|e| format!("Error reading pattern file {}: {}", path, e))?;
+                    // One pattern per line; an empty file has none, so it matches nothing
+                    patterns.extend(text.lines().map(|line| line.trim_end_matches('\r').to_string()));
+                    patterns_from_flags = true;
+                }
+                "--replace" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Expected a template after '--replace'")?;
+                    replace = Some(value.clone());
+                }
+                "--type-list" => {
+                    return Ok(Arguments {
+                        type_list: true,
+                        ..Default::default()
+                    });
+                }
+                flag @ ("-t" | "-T") => {
+                    i += 1;
+                    let name = args.get(i).ok_or(format!("Expected a file type after '{}'", flag))?;
+                    if !types::is_known(name) {
+                        return Err(format!("Unknown file type '{}' (see --type-list)", name));
+                    }
+                    if flag == "-t" {
+                        types.push(name.clone());
                     } else {
-                        files.push(args[i].clone());
+                        types_not.push(name.clone());
+                    }
+                }
+                "--max-matches-total" => {
+                    i += 1;
+                    max_matches_total = Some(parse_number(arg, args.get(i).map(String::as_str))?);
+                }
+                flag @ ("-A" | "--after-context" | "-B" | "--before-context" | "-C" | "--context") => {
+                    let value = match attached {
+                        Some(value) => value,
+                        None => {
+                            i += 1;
+                            args.get(i).ok_or(format!("Expected a number after '{}'", flag))?
+                        }
+                    };
+                    let n = parse_number(flag, Some(value))?;
+                    match flag {
+                        "-A" | "--after-context" => after_context = Some(n),
+                        "-B" | "--before-context" => before_context = Some(n),
+                        _ => context = Some(n),
                     }
                 }
+                "--encoding" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Expected an encoding after '--encoding'")?;
+                    encoding = Encoding::from_name(value).ok_or(format!(
+                        "Unknown encoding '{}' (expected utf8, utf16, utf16le or utf16be)",
+                        value
+                    ))?;
+                }
+                "--step-limit" => {
+                    i += 1;
+                    step_limit = Some(parse_number(arg, args.get(i).map(String::as_str))?);
+                }
+                _ => return Err(format!("Unknown option '{}' (see --help)", arg)),
             }
             i += 1;
         }
         if !use_extended {
             return Err("Expected '-E' flag".to_string());
         }
-        let pattern = pattern.ok_or("Expected a pattern argument".to_string())?;
+        // Without -e or -f, the first positional argument is the pattern
+        let mut positional = positional.into_iter();
+        if !patterns_from_flags {
+            patterns.push(positional.next().ok_or("Expected a pattern argument".to_string())?);
+        }
+        let files = positional.collect();
         Ok(Arguments {
             recursive,
-            pattern,
+            auto_recurse,
+            search_zip,
+            hidden,
+            output_null,
+            encoding,
+            ascii,
+            invert,
+            line_numbers,
+            // --count-matches prints counts in place of lines, like -c
+            count: count || count_matches,
+            count_matches,
+            quiet,
+            // --unique tallies the -o matches, so it implies -o
+            only_matching: only_matching || unique.is_some(),
+            output_separator,
+            unique,
+            before_context: before_context.or(context).unwrap_or(0),
+            after_context: after_context.or(context).unwrap_or(0),
+            case_insensitive,
+            smart_case,
+            strict,
+            match_kind,
+            patterns,
+            invert_file_match,
+            max_matches_total,
+            step_limit,
+            stats,
+            replace,
+            types,
+            types_not,
             files,
+            ..Default::default()
         })
     }
-}
\ No newline at end of file
+}
+
+// Parse the number given as the value of `flag`
+fn parse_number(flag: &str, value: Option<&str>) -> Result<usize, String> {
+    let value = value.ok_or(format!("Expected a number after '{}'", flag))?;
+    value
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid number for '{}': {}", flag, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Arguments, String> {
+        let args: Vec<String> = std::iter::once("grep").chain(args.iter().copied()).map(String::from).collect();
+        Arguments::parse(&args)
+    }
+
+    #[test]
+    fn unknown_options_are_an_error() {
+        assert_eq!(parse(&["-E", "-Q", "x"]).err(), Some("Unknown option '-Q' (see --help)".to_string()));
+        assert_eq!(parse(&["-E", "--colour", "x"]).err(), Some("Unknown option '--colour' (see --help)".to_string()));
+        assert_eq!(
+            parse(&["-E", "--unique=size", "x"]).err(),
+            Some("Unknown order in '--unique=size' (expected lex or count)".to_string())
+        );
+        assert_eq!(parse(&["-E", "-A", "two", "x"]).err(), Some("Invalid number for '-A': two".to_string()));
+        assert_eq!(parse(&["-E", "-e"]).err(), Some("Expected a pattern after '-e'".to_string()));
+        assert_eq!(parse(&["x"]).err(), Some("Expected '-E' flag".to_string()));
+        assert_eq!(parse(&["-E"]).err(), Some("Expected a pattern argument".to_string()));
+    }
+
+    #[test]
+    fn help_and_version_win_over_everything_else() {
+        for args in [&["--help"][..], &["-h"], &["-E", "x", "-h", "--bogus"]] {
+            let arguments = parse(args).ok().unwrap();
+            assert!(arguments.help && !arguments.version);
+        }
+        for args in [&["--version"][..], &["-V"], &["-V", "-E"]] {
+            let arguments = parse(args).ok().unwrap();
+            assert!(arguments.version && !arguments.help);
+        }
+        // An unknown option before --help is reported first
+        assert!(parse(&["--bogus", "--help"]).is_err());
+    }
+
+    // Every flag named in the first column of USAGE's option list, e.g. `-A`
+    // and `--after-context` from `-A N, --after-context N`
+    fn usage_options() -> Vec<&'static str> {
+        USAGE
+            .lines()
+            .filter(|line| line.starts_with("  -"))
+            .flat_map(|line| line.trim_start().split("  ").next().unwrap().split([',', '/']))
+            .map(|spelling| spelling.split_whitespace().next().unwrap())
+            .map(|flag| flag.split(['=', '[']).next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn every_option_in_usage_is_accepted() {
+        let options = usage_options();
+        assert!(options.len() > 40, "{:?}", options);
+        for option in options {
+            // A value for options that take one, then a pattern and a file;
+            // a value that's wrong for the option is fine, an unknown option isn't
+            if let Err(e) = parse(&["-E", option, "1", "x", "file"]) {
+                assert!(!e.starts_with("Unknown option"), "{}: {}", option, e);
+            }
+        }
+    }
+
+    #[test]
+    fn double_dash_ends_the_options() {
+        let arguments = parse(&["-E", "--", "-v", "-n"]).ok().unwrap();
+        assert_eq!(arguments.patterns, ["-v"]);
+        assert_eq!(arguments.files, ["-n"]);
+        assert!(!arguments.invert && !arguments.line_numbers);
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+
+// Text encodings for --encoding. Files are decoded to UTF-8 before matching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // UTF-16 with the byte order taken from a byte order mark, little-endian without one
+    Utf16,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().replace('-', "").as_str() {
+            "utf8" => Some(Encoding::Utf8),
+            "utf16le" => Some(Encoding::Utf16Le),
+            "utf16be" => Some(Encoding::Utf16Be),
+            "utf16" => Some(Encoding::Utf16),
+            _ => None,
+        }
+    }
+}
+
+// Read `source` as lines of UTF-8 text. UTF-16 has no byte-wise line breaks, so
+// it's decoded in full up front. In the default UTF-8 mode a byte order mark
+// still decides: a UTF-8 one is skipped, and a UTF-16 one decodes the rest as
+// UTF-16 (neither is valid UTF-8 text otherwise).
+pub fn reader<'a, R: Read + 'a>(source: R, encoding: Encoding) -> io::Result<Box<dyn BufRead + 'a>> {
+    let mut reader = BufReader::new(source);
+    if encoding == Encoding::Utf8 {
+        match reader.fill_buf()? {
+            [0xEF, 0xBB, 0xBF, ..] => reader.consume(3),
+            [0xFF, 0xFE, ..] | [0xFE, 0xFF, ..] => return decoded(reader, Encoding::Utf16),
+            _ => {}
+        }
+        return Ok(Box::new(reader));
+    }
+    decoded(reader, encoding)
+}
+
+// Decode all of `source` as UTF-16 into an in-memory reader
+fn decoded<'a, R: Read>(mut source: R, encoding: Encoding) -> io::Result<Box<dyn BufRead + 'a>> {
+    let mut bytes = Vec::new();
+    source.read_to_end(&mut bytes)?;
+    Ok(Box::new(Cursor::new(decode_utf16(&bytes, encoding).into_bytes())))
+}
+
+// Decode UTF-16 bytes to a string. A leading byte order mark is dropped (and
+// picks the byte order for `Encoding::Utf16`). Unpaired surrogates and a dangling
+// odd final byte become U+FFFD rather than errors.
+pub fn decode_utf16(bytes: &[u8], encoding: Encoding) -> String {
+    let (big_endian, bytes) = match (encoding, bytes) {
+        (Encoding::Utf16Be, [0xFE, 0xFF, rest @ ..]) => (true, rest),
+        (Encoding::Utf16Le, [0xFF, 0xFE, rest @ ..]) => (false, rest),
+        (Encoding::Utf16, [0xFE, 0xFF, rest @ ..]) => (true, rest),
+        (Encoding::Utf16, [0xFF, 0xFE, rest @ ..]) => (false, rest),
+        (Encoding::Utf16Be, _) => (true, bytes),
+        _ => (false, bytes),
+    };
+    let units = bytes.chunks_exact(2).map(|pair| {
+        if big_endian {
+            u16::from_be_bytes([pair[0], pair[1]])
+        } else {
+            u16::from_le_bytes([pair[0], pair[1]])
+        }
+    });
+    let mut text: String = char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    if bytes.len() % 2 == 1 {
+        text.push(char::REPLACEMENT_CHARACTER);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UTF16LE: &[u8] = include_bytes!("../tests/fixtures/utf16le.log");
+    const UTF16BE: &[u8] = include_bytes!("../tests/fixtures/utf16be.log");
+    const UTF8_BOM: &[u8] = include_bytes!("../tests/fixtures/utf8-bom.log");
+
+    fn lines(bytes: &[u8], encoding: Encoding) -> Vec<String> {
+        reader(bytes, encoding).unwrap().lines().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn decodes_utf16_fixtures() {
+        let expected = ["hi there", "ERROR disk full", "bye"];
+        assert_eq!(lines(UTF16LE, Encoding::Utf16), expected);
+        assert_eq!(lines(UTF16BE, Encoding::Utf16), expected);
+        assert_eq!(lines(UTF16LE, Encoding::Utf16Le), expected);
+        assert_eq!(lines(UTF16BE, Encoding::Utf16Be), expected);
+    }
+
+    #[test]
+    fn default_mode_follows_byte_order_marks() {
+        let expected = ["hi there", "ERROR disk full", "bye"];
+        assert_eq!(lines(UTF8_BOM, Encoding::Utf8), expected);
+        assert_eq!(lines(UTF16LE, Encoding::Utf8), expected);
+        assert_eq!(lines(UTF16BE, Encoding::Utf8), expected);
+        assert_eq!(lines(b"plain\n", Encoding::Utf8), ["plain"]);
+    }
+
+    #[test]
+    fn bad_utf16_becomes_replacement_chars() {
+        // An unpaired high surrogate, then a dangling odd byte
+        assert_eq!(decode_utf16(&[0x00, 0xD8, 0x61, 0x00, 0x62], Encoding::Utf16Le), "\u{FFFD}a\u{FFFD}");
+        assert_eq!(decode_utf16(&[0x00, 0x61], Encoding::Utf16Be), "a");
+        assert_eq!(decode_utf16(&[0x61, 0x00], Encoding::Utf16), "a");
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Encoding::from_name("UTF-16LE"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::from_name("utf16"), Some(Encoding::Utf16));
+        assert_eq!(Encoding::from_name("latin1"), None);
+    }
+}
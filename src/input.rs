@@ -0,0 +1,37 @@
+// Text prepared once for matching against many patterns, so each `Regex` doesn't
+// have to split the same `&str` into chars again
+pub struct Input<'t> {
+    text: &'t str,
+    chars: Vec<char>,
+    // Byte offset of every char, followed by `text.len()` for the end position
+    offsets: Vec<usize>,
+}
+
+impl<'t> Input<'t> {
+    pub fn new(text: &'t str) -> Input<'t> {
+        let mut chars = Vec::with_capacity(text.len());
+        let mut offsets = Vec::with_capacity(text.len() + 1);
+        for (i, ch) in text.char_indices() {
+            chars.push(ch);
+            offsets.push(i);
+        }
+        offsets.push(text.len());
+        Input { text, chars, offsets }
+    }
+
+    // The original text
+    pub fn as_str(&self) -> &'t str {
+        self.text
+    }
+
+    // The text as chars, which is what the matcher works on
+    pub fn chars(&self) -> &[char] {
+        &self.chars
+    }
+
+    // Byte offset in the original text of the char at `char_index`
+    // (`chars().len()` maps to the end of the text)
+    pub fn byte_offset(&self, char_index: usize) -> usize {
+        self.offsets[char_index]
+    }
+}
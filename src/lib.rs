@@ -0,0 +1,17 @@
+pub mod parser;
+pub mod matcher;
+mod analysis;
+#[cfg(feature = "pattern-cache")]
+mod cache;
+mod input;
+mod nfa;
+mod regex;
+mod replace;
+mod unicode_tables;
+
+pub use input::Input;
+pub use matcher::{grep_str, MatchError, MatchKind, MatchOptions, MatchResult};
+pub use regex::{CaptureMatches, Captures, Match, Matches, Regex};
+pub use replace::Template;
+#[cfg(feature = "pattern-cache")]
+pub use cache::set_pattern_cache_capacity;
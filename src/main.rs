@@ -1,16 +1,20 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::env;
 use std::fs::{File, read_dir};
 use std::path::Path;
 use std::io;
-use std::io::{prelude::*, BufReader};
+use std::io::prelude::*;
 use std::process;
 
-mod parser;
-mod matcher;
+mod archive;
 mod cli;
+mod encoding;
+mod types;
 
-use matcher::match_pattern;
-use cli::Arguments;
+use codecrafters_grep::{MatchError, Regex, Template};
+use cli::{Arguments, UniqueOrder};
+use encoding::Encoding;
 
 // Usage: echo <input_text> | your_program.sh -E <pattern>
 fn main() {
@@ -23,52 +27,266 @@ fn main() {
         }
     };
 
-    let mut files = Vec::new();
-    if !arguments.files.is_empty() {
+    if arguments.help {
+        written(write!(io::stdout().lock(), "{}", cli::USAGE));
+        process::exit(0);
+    }
+    if arguments.version {
+        written(writeln!(io::stdout().lock(), "{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+        process::exit(0);
+    }
 
-        // Take input from files
-        if arguments.recursive {
-            for target in &arguments.files {
-                collect_files_recursively(Path::new(target), &mut files);
-            }
-        } else {
-            files = arguments.files.clone();
+    if arguments.type_list {
+        written(types::write_type_list(&mut io::stdout().lock()));
+        process::exit(0);
+    }
+
+    if let Some(warning) = pattern_file_warning(&arguments) {
+        eprintln!("warning: {}", warning);
+    }
+
+    let ignore_case = ignores_case(&arguments);
+
+    // Compile once up front, rejecting malformed patterns before reading any input.
+    // Like grep, a bad pattern or template exits with status 2, not 1 for "no match".
+    // Several -e/-f patterns become one regex matching wherever any of them does.
+    let compiled = match arguments.patterns.as_slice() {
+        [pattern] => Regex::new(pattern),
+        patterns => Regex::new_any(patterns),
+    };
+    let regex = match compiled {
+        Ok(r) => with_step_limit(
+            r.match_kind(arguments.match_kind)
+                .ascii(arguments.ascii)
+                .case_insensitive(ignore_case),
+            &arguments,
+        ),
+        Err(e) => {
+            eprintln!("Invalid pattern: {}", e);
+            process::exit(2);
         }
-        match_files(&files, &arguments.pattern);
-    } else {
+    };
+    if let Some(warning) = regex.warning() {
+        if arguments.strict {
+            eprintln!("Invalid pattern: {} (rejected by --strict)", warning);
+            process::exit(2);
+        }
+        eprintln!("warning: {}", warning);
+    }
 
+    // Files containing a match for this pattern are skipped entirely
+    let exclude = arguments.invert_file_match.as_ref().map(|p| match Regex::new(p) {
+        Ok(r) => with_step_limit(r.ascii(arguments.ascii).case_insensitive(ignore_case), &arguments),
+        Err(e) => {
+            eprintln!("Invalid pattern for --invert-file-match: {}", e);
+            process::exit(2);
+        }
+    });
+
+    // Parse the --replace template once rather than for every matching line
+    let replace = arguments.replace.as_ref().map(|t| match regex.template(t) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Invalid replacement: {}", e);
+            process::exit(2);
+        }
+    });
+
+    let mut search = FileSearch {
+        output: LineOutput {
+            regex: &regex,
+            replace: replace.as_ref(),
+            invert: arguments.invert,
+            line_numbers: arguments.line_numbers,
+            count: arguments.count,
+            count_matches: arguments.count_matches,
+            quiet: arguments.quiet,
+            only_matching: arguments.only_matching,
+            separator: arguments.output_separator.as_deref(),
+            before: arguments.before_context,
+            after: arguments.after_context,
+            terminator: if arguments.output_null { '\0' } else { '\n' },
+        },
+        exclude: exclude.as_ref(),
+        arguments: &arguments,
+        progress: Progress {
+            budget: arguments.max_matches_total,
+            unique: arguments.unique.map(|_| HashMap::new()),
+            printed_group: false,
+            stats: arguments.stats.then(|| vec![0; arguments.patterns.len()]),
+        },
+        any_match: false,
+        // A directory can turn up any number of files, so always say which one matched
+        show_labels: arguments.files.len() > 1
+            || ((arguments.recursive || arguments.auto_recurse)
+                && arguments.files.first().is_some_and(|f| Path::new(f).is_dir())),
+    };
+
+    if arguments.files.is_empty() {
         // Take input from stdin
-        let mut input_line = String::new();
-        io::stdin().read_line(&mut input_line).unwrap();
-        let trimmed_input = input_line.trim_end_matches('\n');
+        search.search_stdin();
+    }
 
-        if match_pattern(trimmed_input, &arguments.pattern) {
-            process::exit(0)
-        } else {
-            process::exit(1)
+    // Take input from files, searching each one as soon as it is found
+    for target in &arguments.files {
+        visit_target(target, &arguments, &mut |file_name| {
+            search.search_file(file_name);
+            !search.is_done()
+        });
+        if search.is_done() {
+            break;
+        }
+    }
+
+    if let (Some(order), Some(tally)) = (arguments.unique, &search.progress.unique) {
+        print_unique(&mut io::stdout().lock(), tally, order, search.output.terminator);
+    }
+    if let Some(stats) = &search.progress.stats {
+        print_stats(&mut io::stderr().lock(), &arguments.patterns, stats);
+    }
+
+    if search.any_match {
+        process::exit(0)
+    } else {
+        process::exit(1)
+    }
+}
+
+// Visit the files named by one command-line target: the target itself, or with
+// -r every file under it. Like grep, a directory without -r is reported and
+// skipped, unless --auto-recurse. Returns false once `visit` asks to stop.
+fn visit_target(target: &str, arguments: &Arguments, visit: &mut dyn FnMut(&str) -> bool) -> bool {
+    let is_dir = Path::new(target).is_dir();
+    if is_dir && !arguments.recursive {
+        if !arguments.auto_recurse {
+            eprintln!("{}: Is a directory", target);
+            return true;
         }
+        eprintln!("warning: {} is a directory; searching it recursively", target);
+    }
+    if is_dir || arguments.recursive {
+        walk_files_recursively(Path::new(target), arguments, visit)
+    } else {
+        visit(target)
     }
 }
-fn collect_files_recursively(path: &Path, files: &mut Vec<String>) {
+
+// Call `visit` with every file under `path` as it is found, stopping early once
+// `visit` returns false. Returns false if the walk was stopped.
+fn walk_files_recursively(path: &Path, arguments: &Arguments, visit: &mut dyn FnMut(&str) -> bool) -> bool {
     if path.is_file() {
-        files.push(path.to_string_lossy().to_string());
+        return visit(&path.to_string_lossy());
     } else if path.is_dir() {
         if let Ok(entries) = read_dir(path) {
+            // read_dir's order depends on the filesystem, so sort by name for stable output
+            let mut entries: Vec<_> = entries.flatten().collect();
+            entries.sort_by_key(|entry| entry.file_name());
             for entry in entries {
-                if let Ok(entry) = entry {
-                    let entry_path = entry.path();
-                    collect_files_recursively(&entry_path, files);
+                let entry_path = entry.path();
+                // Dotfiles and dot-directories (like .git) are skipped unless --hidden
+                if !arguments.hidden && entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                // -t/-T only filter files found by recursion; named files are always searched
+                if entry_path.is_file() && !type_allowed(&entry_path, arguments) {
+                    continue;
+                }
+                if !walk_files_recursively(&entry_path, arguments, visit) {
+                    return false;
                 }
             }
         }
     }
+    true
+}
+
+// Does the file pass the -t (include) and -T (exclude) type filters?
+fn type_allowed(path: &Path, arguments: &Arguments) -> bool {
+    let file_name = match path.file_name() {
+        Some(n) => n.to_string_lossy(),
+        None => return true,
+    };
+    let selected = arguments.types.is_empty()
+        || arguments.types.iter().any(|t| types::matches(t, &file_name));
+    let excluded = arguments.types_not.iter().any(|t| types::matches(t, &file_name));
+    selected && !excluded
 }
 
-fn match_files(files: &[String], pattern: &str) {
-    let mut any_match = false;
-    let multiple_files = files.len() > 1;
+// Everything needed to search one file after another, plus the results so far
+struct FileSearch<'a> {
+    output: LineOutput<'a>,
+    exclude: Option<&'a Regex>,
+    arguments: &'a Arguments,
+    progress: Progress,
+    any_match: bool,
+    show_labels: bool,
+}
+
+// What match_lines keeps track of from one file to the next
+struct Progress {
+    // Matches still allowed by --max-matches-total
+    budget: Option<usize>,
+    // --unique: how often each -o match was seen, printed once the search ends
+    unique: Option<HashMap<String, usize>>,
+    // Has a context group been printed, so the next one needs a `--` before it?
+    printed_group: bool,
+    // --stats: how many lines each pattern has matched so far, in pattern order
+    stats: Option<Vec<usize>>,
+}
+
+// Which lines get printed and how; the same for every file searched
+struct LineOutput<'a> {
+    regex: &'a Regex,
+    replace: Option<&'a Template>,
+    // -v: select the lines that don't match instead
+    invert: bool,
+    // -n: prefix each line with its 1-based number in the file
+    line_numbers: bool,
+    // -c: print only how many lines were selected in each file
+    count: bool,
+    // --count-matches: with `count`, count the matches in each selected line
+    // instead of the line itself
+    count_matches: bool,
+    // -q: print nothing, not even the -c count, and stop at the first selected line
+    quiet: bool,
+    // -o: print each match on its own line instead of the whole line
+    only_matching: bool,
+    // --output-separator: with -o, print a line's matches together, joined by this
+    separator: Option<&'a str>,
+    // -B / -A: how many lines of context to print before and after each selected line
+    before: usize,
+    after: usize,
+    // Ends each printed line: `\n`, or `\0` with --output-null
+    terminator: char,
+}
+
+impl FileSearch<'_> {
+    // Has --max-matches-total been reached, or with -q any line been selected, so
+    // no more files need opening?
+    fn is_done(&self) -> bool {
+        self.progress.budget == Some(0) || (self.output.quiet && self.any_match)
+    }
+
+    // Search every line of stdin. Empty input has no lines at all, so not even
+    // `^$` can match it.
+    fn search_stdin(&mut self) {
+        let (output, progress) = (&self.output, &mut self.progress);
+        self.any_match |= match_lines(&mut io::stdin().lock(), None, output, progress, &mut io::stdout().lock());
+    }
+
+    fn search_file(&mut self, file_name: &str) {
+        if self.is_done() {
+            return;
+        }
+
+        // An archive isn't skipped as a whole; its members are checked one by one
+        let is_archive = self.arguments.search_zip && archive::is_zip(file_name);
+        if let Some(exclude) = self.exclude {
+            if !is_archive && file_contains_match(file_name, exclude, self.arguments.encoding) {
+                return;
+            }
+        }
 
-    for file_name in files {
         // Open the file and read each line
         let file = match File::open(file_name) {
             Ok(f) => f,
@@ -78,24 +296,920 @@ fn match_files(files: &[String], pattern: &str) {
             }
         };
 
-        let reader = BufReader::new(file);
+        let (output, progress, exclude) = (&self.output, &mut self.progress, self.exclude);
+        let encoding = self.arguments.encoding;
+        if is_archive {
+            // Archive members are virtual files and always get an `archive.zip!member:` prefix
+            self.any_match |= archive::search_zip(file_name, file, &mut |label, member| {
+                search_member(member, label, encoding, exclude, output, progress, &mut io::stdout().lock())
+            });
+            return;
+        }
+
+        let label = if self.show_labels { Some(file_name) } else { None };
+        let mut reader = match encoding::reader(file, encoding) {
+            Ok(reader) => reader,
+            Err(e) => {
+                eprintln!("Error reading {}: {}", file_name, e);
+                return;
+            }
+        };
+        self.any_match |= match_lines(&mut reader, label, output, progress, &mut io::stdout().lock());
+    }
+}
+
+// Search one archive member, decoded and excluded just as a plain file would be.
+// A member can only be read once, so it is buffered for the --invert-file-match
+// pre-scan.
+fn search_member(
+    member: &mut dyn Read,
+    label: &str,
+    encoding: Encoding,
+    exclude: Option<&Regex>,
+    output: &LineOutput,
+    progress: &mut Progress,
+    out: &mut dyn Write,
+) -> bool {
+    let mut bytes = Vec::new();
+    if let Some(exclude) = exclude {
+        if let Err(e) = member.read_to_end(&mut bytes) {
+            eprintln!("Error reading {}: {}", label, e);
+            return false;
+        }
+        if contains_match(&bytes[..], exclude, encoding, label) {
+            return false;
+        }
+    }
+    let reader = match exclude {
+        Some(_) => encoding::reader(&bytes[..], encoding),
+        None => encoding::reader(member, encoding),
+    };
+    match reader {
+        Ok(mut reader) => match_lines(&mut reader, Some(label), output, progress, out),
+        Err(e) => {
+            eprintln!("Error reading {}: {}", label, e);
+            false
+        }
+    }
+}
+
+// Pre-scan a file for any line matching `regex`, stopping at the first hit. The
+// file is decoded just as the main search decodes it. Unreadable files are left
+// for the main search to report.
+fn file_contains_match(file_name: &str, regex: &Regex, encoding: Encoding) -> bool {
+    match File::open(file_name) {
+        Ok(file) => contains_match(file, regex, encoding, file_name),
+        Err(_) => false,
+    }
+}
+
+// Decode `source` and look for any line matching `regex`
+fn contains_match(source: impl Read, regex: &Regex, encoding: Encoding, label: &str) -> bool {
+    let reader = match encoding::reader(source, encoding) {
+        Ok(reader) => reader,
+        Err(_) => return false,
+    };
+    reader
+        .lines()
+        .map_while(Result::ok)
+        .any(|line| or_exit(regex.is_match(&line), label))
+}
+
+// `-E file.txt` takes file.txt as the pattern and searches stdin, which is
+// rarely what was meant. Say so when the only pattern names an existing file and
+// no input file was given; the search of stdin still goes ahead.
+fn pattern_file_warning(arguments: &Arguments) -> Option<String> {
+    match (arguments.patterns.as_slice(), arguments.files.is_empty()) {
+        ([pattern], true) if Path::new(pattern).is_file() => Some(format!(
+            "pattern '{}' is also an existing file, but no input file was given; searching stdin (usage: -E <pattern> [file...])",
+            pattern
+        )),
+        _ => None,
+    }
+}
+
+// -i always ignores case; -S only when no pattern has an uppercase letter
+fn ignores_case(arguments: &Arguments) -> bool {
+    arguments.case_insensitive || (arguments.smart_case && !arguments.patterns.iter().any(|p| has_uppercase(p)))
+}
+
+// Does the pattern contain an uppercase letter, for --smart-case? Only letters
+// that stand for themselves count. Escapes such as `\D`, `\p{Greek}` or
+// `\x{4A}` and group names such as the one in `(?P<Name>...)` are syntax.
+fn has_uppercase(pattern: &str) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    // Skip past `close` if `chars[i]` opens an argument like `{Greek}` or `<Name>`
+    let skip_argument = |i: usize, open: char, close: char| -> usize {
+        if chars.get(i) == Some(&open) {
+            chars[i..].iter().position(|&c| c == close).map_or(chars.len(), |n| i + n + 1)
+        } else {
+            i
+        }
+    };
+    let mut i = 0;
+    while i < chars.len() {
+        let rest = &chars[i..];
+        if rest[0] == '\\' {
+            i = match rest.get(1) {
+                Some('p' | 'P') if rest.get(2) == Some(&'{') => skip_argument(i + 2, '{', '}'),
+                // `\pL`: a one-letter property name
+                Some('p' | 'P') => i + 3,
+                Some('x' | 'u') if rest.get(2) == Some(&'{') => skip_argument(i + 2, '{', '}'),
+                Some('x') => i + 4,
+                Some('u') => i + 6,
+                Some('k') => skip_argument(i + 2, '<', '>'),
+                // Quoted text is all literal, backslashes included
+                Some('Q') => {
+                    let quoted = &rest[2..];
+                    let end = quoted.windows(2).position(|w| w == ['\\', 'E']).unwrap_or(quoted.len());
+                    if quoted[..end].iter().any(|c| c.is_uppercase()) {
+                        return true;
+                    }
+                    i + 2 + end
+                }
+                _ => i + 2,
+            };
+        } else if rest.starts_with(&['(', '?', 'P']) {
+            // `(?P<name>`, `(?P=name)` and `(?P>name)`
+            i = rest.iter().position(|&c| c == '>' || c == ')').map_or(chars.len(), |n| i + n + 1);
+        } else if rest.starts_with(&['(', '?', '<']) && !matches!(rest.get(3), Some('=' | '!')) {
+            i = skip_argument(i + 2, '<', '>');
+        } else if rest[0].is_uppercase() {
+            return true;
+        } else {
+            i += 1;
+        }
+    }
+    false
+}
+
+// Apply --step-limit, if given
+fn with_step_limit(regex: Regex, arguments: &Arguments) -> Regex {
+    match arguments.step_limit {
+        Some(limit) => regex.step_limit(limit),
+        None => regex,
+    }
+}
+
+// Unwrap a match result, or stop with exit status 2 when a search was abandoned
+// (grep's status for "an error occurred", as opposed to "no match")
+fn or_exit<T>(result: Result<T, MatchError>, label: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("Error searching {}: {}", label, e);
+            process::exit(2);
+        }
+    }
+}
 
-        for line in reader.lines() {
-            let line = line.unwrap();
-            let trimmed_line = line.trim_end_matches('\n');
-            if match_pattern(trimmed_line, &pattern) {
-                if multiple_files {
-                    print!("{}:", file_name);
+// Write the selected lines of one (possibly virtual) file to `out`, prefixed
+// with `label:` when given (then the line number with -n), until the
+// --max-matches-total budget of lines (or -o matches) runs out. With a replace
+// template, each matching line is printed with its matches substituted. Returns
+// whether any line was selected, which with -v means any line that didn't
+// match. With -c, only the number of selected lines (or with --count-matches,
+// of their matches) is printed, after the last one. With -o, each non-empty
+// match is printed instead (all of a line's together with --output-separator),
+// or tallied for --unique; a replace template is expanded for each match. With
+// --stats, each matching line is also counted against every pattern that
+// matches it.
+//
+// With -B/-A, up to `before` unselected lines are held back in case a selected
+// line follows, and `after` lines are printed once one has. Overlapping windows
+// merge, and `--` separates groups only where lines were skipped.
+fn match_lines(
+    reader: &mut dyn BufRead,
+    label: Option<&str>,
+    output: &LineOutput,
+    progress: &mut Progress,
+    out: &mut dyn Write,
+) -> bool {
+    let LineOutput {
+        regex,
+        replace,
+        invert,
+        line_numbers,
+        count,
+        count_matches,
+        quiet,
+        only_matching,
+        separator,
+        before,
+        after,
+        terminator,
+    } = *output;
+    // Context only applies when whole lines are printed
+    let context = !count && !only_matching && (before > 0 || after > 0);
+    let number = |index: usize| if line_numbers { Some(index + 1) } else { None };
+    let mut selected = 0;
+    // What -c prints: the selected lines, or with --count-matches their matches
+    let mut counted = 0;
+    let mut held: VecDeque<(usize, String)> = VecDeque::new();
+    let mut after_left = 0;
+    let mut last_printed: Option<usize> = None;
+    // Numbered from every line read, not just the printed ones
+    for (index, line) in reader.lines().enumerate() {
+        let exhausted = progress.budget == Some(0);
+        // Once the budget is spent, only the last match's after-context is left
+        if exhausted && after_left == 0 {
+            break;
+        }
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("Error reading {}: {}", label.unwrap_or("input"), e);
+                break;
+            }
+        };
+        let matched = !exhausted && or_exit(regex.is_match(&line), label.unwrap_or("input"));
+        if let (true, Some(stats)) = (matched, &mut progress.stats) {
+            for i in or_exit(regex.matching_patterns(&line), label.unwrap_or("input")) {
+                stats[i] += 1;
+            }
+        }
+        if exhausted || matched == invert {
+            if !context {
+                continue;
+            }
+            if after_left > 0 {
+                after_left -= 1;
+                last_printed = Some(index);
+                print_line(out, label, number(index), '-', &line, terminator);
+            } else if before > 0 {
+                if held.len() == before {
+                    held.pop_front();
+                }
+                held.push_back((index, line));
+            }
+            continue;
+        }
+
+        selected += 1;
+        // One selected line settles the exit status, and -q prints nothing
+        if quiet {
+            return true;
+        }
+        // Under -o the budget is spent per match instead, below
+        if let (Some(remaining), false) = (&mut progress.budget, only_matching && !count) {
+            *remaining -= 1;
+        }
+        if count {
+            // An inverted line has no matches, so it counts once
+            if count_matches && !invert {
+                for m in regex.find_iter(&line) {
+                    or_exit(m, label.unwrap_or("input"));
+                    counted += 1;
                 }
-                println!("{}", line);
-                any_match = true;
+            } else {
+                counted += 1;
+            }
+            continue;
+        }
+        if only_matching {
+            // An inverted line has no matches, so -o -v prints nothing
+            let mut matches = only_matches(regex, &line, replace.filter(|_| !invert), label.unwrap_or("input"));
+            // Each match counts against --max-matches-total, so the budget can run
+            // out partway through a line
+            if let Some(remaining) = &mut progress.budget {
+                matches.truncate(*remaining);
+                *remaining -= matches.len();
+            }
+            let mut joined: Vec<&str> = Vec::new();
+            for m in &matches {
+                match (&mut progress.unique, separator) {
+                    (Some(tally), _) => *tally.entry(m.to_string()).or_insert(0) += 1,
+                    (None, Some(_)) => joined.push(m),
+                    (None, None) => print_line(out, label, number(index), ':', m, terminator),
+                }
+            }
+            if let (Some(separator), false) = (separator, joined.is_empty()) {
+                print_line(out, label, number(index), ':', &joined.join(separator), terminator);
+            }
+            continue;
+        }
+        if context {
+            let first = held.front().map_or(index, |(i, _)| *i);
+            // A gap since the last printed line (or a new file) starts a new group
+            let gap = match last_printed {
+                Some(last) => first > last + 1,
+                None => true,
+            };
+            if progress.printed_group && gap {
+                written(write!(out, "--{}", terminator));
+            }
+            for (i, held_line) in held.drain(..) {
+                print_line(out, label, number(i), '-', &held_line, terminator);
             }
+            progress.printed_group = true;
+            after_left = after;
+            last_printed = Some(index);
+        }
+        // An inverted line has no matches, so there is nothing to replace
+        match replace.filter(|_| !invert) {
+            Some(template) => {
+                let replaced = or_exit(regex.replace_all_with(&line, template), label.unwrap_or("input"));
+                print_line(out, label, number(index), ':', &replaced, terminator)
+            }
+            None => print_line(out, label, number(index), ':', &line, terminator),
+        }
+    }
+    if count && !quiet {
+        match label {
+            Some(label) => written(write!(out, "{}:{}{}", label, counted, terminator)),
+            None => written(write!(out, "{}{}", counted, terminator)),
         }
     }
+    selected > 0
+}
 
-    if any_match {
-        process::exit(0)
-    } else {
-        process::exit(1)
+// Print one line of output after its `label` and line `number`, if any. Like
+// grep, each prefix is followed by `sep`: `:` for a selected line, `-` for context.
+fn print_line(out: &mut dyn Write, label: Option<&str>, number: Option<usize>, sep: char, text: &str, terminator: char) {
+    if let Some(label) = label {
+        written(write!(out, "{}{}", label, sep));
+    }
+    if let Some(number) = number {
+        written(write!(out, "{}{}", number, sep));
+    }
+    written(write!(out, "{}{}", text, terminator));
+}
+
+// Stop once the output can't be written to, quietly if its reader has gone
+// away (as in `| head`)
+fn written(result: io::Result<()>) {
+    if let Err(e) = result {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("Error writing output: {}", e);
+        }
+        process::exit(2);
+    }
+}
+
+// What -o prints for each non-empty match in `line`: the matched text, or the
+// expanded replace template. Zero-width matches (from `a*`, `^` or `\b`) have no
+// text to show, so they are skipped.
+fn only_matches<'h>(regex: &Regex, line: &'h str, replace: Option<&Template>, label: &str) -> Vec<Cow<'h, str>> {
+    match replace {
+        Some(template) => regex
+            .captures_iter(line)
+            .map(|caps| or_exit(caps, label))
+            .filter(|caps| caps.get(0).is_some_and(|m| m.start < m.end))
+            .map(|caps| Cow::Owned(caps.expand(template)))
+            .collect(),
+        None => regex
+            .find_iter(line)
+            .map(|m| or_exit(m, label))
+            .filter(|m| m.start < m.end)
+            .map(|m| Cow::Borrowed(m.as_str()))
+            .collect(),
+    }
+}
+
+// Print the --stats count of lines each pattern matched, in the order the
+// patterns were given, so patterns that never matched show up as 0
+fn print_stats(out: &mut dyn Write, patterns: &[String], stats: &[usize]) {
+    for (pattern, n) in patterns.iter().zip(stats) {
+        written(writeln!(out, "{:>7} {}", n, pattern));
+    }
+}
+
+// Print each distinct --unique value once: sorted, or with its count like
+// `sort | uniq -c | sort -rn` (ties broken alphabetically)
+fn print_unique(out: &mut dyn Write, tally: &HashMap<String, usize>, order: UniqueOrder, terminator: char) {
+    let mut values: Vec<(&String, &usize)> = tally.iter().collect();
+    match order {
+        UniqueOrder::Lexical => {
+            values.sort();
+            for (value, _) in values {
+                written(write!(out, "{}{}", value, terminator));
+            }
+        }
+        UniqueOrder::Count => {
+            values.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            for (value, n) in values {
+                written(write!(out, "{:>7} {}{}", n, value, terminator));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    // Run the line matcher over `input` as the command line `args` would (less
+    // the file handling), returning what it printed and whether anything matched
+    fn grep(args: &[&str], input: &str) -> (String, bool) {
+        grep_each(args, &[input])
+    }
+
+    // Like `grep`, but over several inputs in turn, as if they were files; each
+    // is labelled with the file argument in the same position, if any
+    fn grep_each(args: &[&str], inputs: &[&str]) -> (String, bool) {
+        searching(args, |arguments, output, progress, out| {
+            let mut matched = false;
+            for (i, input) in inputs.iter().enumerate() {
+                let label = arguments.files.get(i).map(String::as_str);
+                matched |= match_lines(&mut input.as_bytes(), label, output, progress, out);
+            }
+            matched
+        })
+    }
+
+    // Set up the output for `args` as main would and run `search` with it
+    fn searching(
+        args: &[&str],
+        search: impl FnOnce(&Arguments, &LineOutput, &mut Progress, &mut Vec<u8>) -> bool,
+    ) -> (String, bool) {
+        let arguments = parse(args);
+        let regex = with_step_limit(
+            Regex::new_any(&arguments.patterns)
+                .unwrap()
+                .match_kind(arguments.match_kind)
+                .ascii(arguments.ascii)
+                .case_insensitive(ignores_case(&arguments)),
+            &arguments,
+        );
+        let replace = arguments.replace.as_ref().map(|t| regex.template(t).unwrap());
+        let output = LineOutput {
+            regex: &regex,
+            replace: replace.as_ref(),
+            invert: arguments.invert,
+            line_numbers: arguments.line_numbers,
+            count: arguments.count,
+            count_matches: arguments.count_matches,
+            quiet: arguments.quiet,
+            only_matching: arguments.only_matching,
+            separator: arguments.output_separator.as_deref(),
+            before: arguments.before_context,
+            after: arguments.after_context,
+            terminator: if arguments.output_null { '\0' } else { '\n' },
+        };
+        let mut progress = Progress {
+            budget: arguments.max_matches_total,
+            unique: arguments.unique.map(|_| HashMap::new()),
+            printed_group: false,
+            stats: arguments.stats.then(|| vec![0; arguments.patterns.len()]),
+        };
+        let mut out = Vec::new();
+        let matched = search(&arguments, &output, &mut progress, &mut out);
+        if let (Some(order), Some(tally)) = (arguments.unique, &progress.unique) {
+            print_unique(&mut out, tally, order, output.terminator);
+        }
+        // main prints these to stderr, after everything else
+        if let Some(stats) = &progress.stats {
+            print_stats(&mut out, &arguments.patterns, stats);
+        }
+        (String::from_utf8(out).unwrap(), matched)
+    }
+
+    fn parse(args: &[&str]) -> Arguments {
+        let args: Vec<String> = std::iter::once("grep").chain(args.iter().copied()).map(String::from).collect();
+        Arguments::parse(&args).unwrap()
+    }
+
+    // A fresh directory under the system temp dir, removed with everything in it
+    // when dropped, so a failing test doesn't leave it behind
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = env::temp_dir().join(format!("rusty-grep-{}-{}", name, process::id()));
+            // Left over from a run that was killed
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+
+        // Write `contents` to `file` (a path relative to the directory), creating
+        // any directories it needs
+        fn write(&self, file: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn smart_case_ignores_case_only_for_lowercase_patterns() {
+        let matches = |args: &[&str], text: &str| {
+            let arguments = parse(args);
+            Regex::new(&arguments.patterns[0]).unwrap().case_insensitive(ignores_case(&arguments)).is_match(text)
+        };
+        assert_eq!(matches(&["-S", "-E", "foo"], "FOO"), Ok(true));
+        assert_eq!(matches(&["-S", "-E", "Foo"], "FOO"), Ok(false));
+        assert_eq!(matches(&["-S", "-E", "Foo"], "Foo"), Ok(true));
+        assert_eq!(matches(&["-S", "-i", "-E", "Foo"], "FOO"), Ok(true));
+        assert_eq!(matches(&["-E", "foo"], "FOO"), Ok(false));
+        // One uppercase pattern among several makes them all case-sensitive
+        assert!(!ignores_case(&parse(&["-S", "-E", "-e", "foo", "-e", "Bar"])));
+    }
+
+    #[test]
+    fn ignore_case_finds_a_plain_word_in_any_case() {
+        let input = "An ERROR here\nerr\nno Error\nTERRORS\n";
+        assert_eq!(grep(&["-E", "-i", "-n", "error"], input), ("1:An ERROR here\n3:no Error\n4:TERRORS\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-i", "-c", "ERROR"], input), ("3\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-i", "-o", "error"], input), ("ERROR\nError\nERROR\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-i", "σίσυφος"], "ΣΊΣΥΦΟΣ\n"), ("ΣΊΣΥΦΟΣ\n".to_string(), true));
+    }
+
+    #[test]
+    fn a_pattern_naming_a_file_only_warns() {
+        let file = fixture("utf8-bom.log");
+        let warning = pattern_file_warning(&parse(&["-E", &file])).unwrap();
+        assert!(warning.contains(&format!("'{}' is also an existing file", file)), "{}", warning);
+        // With an input file, or patterns from -e, the first positional is unambiguous
+        assert_eq!(pattern_file_warning(&parse(&["-E", &file, &file])), None);
+        assert_eq!(pattern_file_warning(&parse(&["-E", "-e", "x", &file])), None);
+        assert_eq!(pattern_file_warning(&parse(&["-E", "no-such-file.txt"])), None);
+        // A directory isn't something one would mean to search without -r
+        assert_eq!(pattern_file_warning(&parse(&["-E", env!("CARGO_MANIFEST_DIR")])), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn escapes_and_group_names_are_not_uppercase() {
+        for pattern in [
+            "foo", "\\D\\W\\S", "\\p{Greek}", "\\P{Lu}x", "\\pL", "\\x4A", "\\x{4A}", "\\u004A",
+            "(?P<Name>a)", "(?<Name>a)(?P=Name)", "(?<Name>a)\\k<Name>", "[\\p{Greek}\\D]", "(?i)a",
+            "\\Qa.b\\E\\D", "\\Q\\E",
+        ] {
+            assert!(!has_uppercase(pattern), "{}", pattern);
+        }
+        for pattern in ["Foo", "\\p{Greek}X", "(?P<x>A)", "(?<=A)b", "(?<!A)b", "[A-Z]", "\\d+É", "\\Q\\D\\E", "\\Qa\\pL"] {
+            assert!(has_uppercase(pattern), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn file_pre_scan_decodes_like_the_search() {
+        let regex = Regex::new("hi").unwrap();
+        for (name, encoding) in [
+            ("utf16le.log", Encoding::Utf16),
+            ("utf16le.log", Encoding::Utf16Le),
+            ("utf16be.log", Encoding::Utf16),
+            ("utf16le.log", Encoding::Utf8),
+            ("utf8-bom.log", Encoding::Utf8),
+        ] {
+            assert!(file_contains_match(&fixture(name), &regex, encoding), "{} as {:?}", name, encoding);
+        }
+        assert!(!file_contains_match(&fixture("utf16le.log"), &Regex::new("warning").unwrap(), Encoding::Utf16));
+        assert!(!file_contains_match(&fixture("missing.log"), &regex, Encoding::Utf8));
+    }
+
+    #[test]
+    fn archive_members_are_decoded_like_files() {
+        for (name, encoding) in [("utf16le.log", Encoding::Utf8), ("utf16be.log", Encoding::Utf16), ("utf8-bom.log", Encoding::Utf8)] {
+            let bytes = std::fs::read(fixture(name)).unwrap();
+            let (out, matched) = searching(&["-E", "^(ERROR|hi)"], |_, output, progress, out| {
+                search_member(&mut &bytes[..], "logs.zip!m.log", encoding, None, output, progress, out)
+            });
+            assert!(matched, "{}", name);
+            assert_eq!(out, "logs.zip!m.log:hi there\nlogs.zip!m.log:ERROR disk full\n", "{}", name);
+        }
+    }
+
+    #[test]
+    fn invert_file_match_skips_archive_members() {
+        let exclude = Regex::new("DO NOT EDIT").unwrap();
+        let mut matched = Vec::new();
+        let (out, _) = searching(&["-E", "TODO"], |_, output, progress, out| {
+            for (label, text) in [("a.zip!gen.rs", "// DO NOT EDIT\n// TODO\n"), ("a.zip!lib.rs", "// TODO\n")] {
+                matched.push(search_member(&mut text.as_bytes(), label, Encoding::Utf8, Some(&exclude), output, progress, out));
+            }
+            true
+        });
+        assert_eq!(out, "a.zip!lib.rs:// TODO\n");
+        assert_eq!(matched, [false, true]);
+    }
+
+    #[test]
+    fn output_null_ends_every_printed_line_with_nul() {
+        let input = "a\nb\nab\n";
+        assert_eq!(grep(&["-E", "a", "--output-null"], input), ("a\0ab\0".to_string(), true));
+        assert_eq!(grep(&["-E", "a", "--output-null", "-n"], input).0, "1:a\x003:ab\0");
+        assert_eq!(grep(&["-E", "a", "--output-null", "-o"], input).0, "a\0a\0");
+        assert_eq!(grep(&["-E", "a", "--output-null", "-c"], input).0, "2\0");
+        assert_eq!(grep_each(&["-E", "b", "--output-null", "x", "y"], &["b\n", "ab\n"]).0, "x:b\0y:ab\0");
+        assert_eq!(grep(&["-E", "z", "--output-null"], input), (String::new(), false));
+    }
+
+    #[test]
+    fn output_separator_joins_a_lines_matches() {
+        let input = "a=1, b=22, c=333\nnone\nx=4\n";
+        assert_eq!(grep(&["-E", "-o", "--output-separator=,", "\\d+"], input), ("1,22,333\n4\n".to_string(), true));
+        assert_eq!(
+            grep(&["-E", "-o", "-n", "--output-separator", " | ", "\\d+", "f.txt"], input),
+            ("f.txt:1:1 | 22 | 333\nf.txt:3:4\n".to_string(), true)
+        );
+        // Without the option each match gets a line of its own
+        assert_eq!(grep(&["-E", "-o", "\\d+"], "1 22\n"), ("1\n22\n".to_string(), true));
+        // Only zero-width matches: nothing to join
+        assert_eq!(grep(&["-E", "-o", "--output-separator=,", "\\b"], "ab\n"), (String::new(), true));
+        assert_eq!(grep(&["-E", "--unique", "--output-separator=,", "\\d"], "1 2 1\n"), ("1\n2\n".to_string(), true));
+    }
+
+    #[test]
+    fn replace_rewrites_each_matching_line() {
+        let input = "name=ann\nskip\nage=41\n";
+        assert_eq!(
+            grep(&["-E", "--replace", "${1}: $2$$", "(\\w+)=(\\w+)"], input),
+            ("name: ann$\nage: 41$\n".to_string(), true)
+        );
+        assert_eq!(
+            grep(&["-E", "-n", "--replace", "<${v}>", "=(?P<v>\\d+)", "f.txt"], input),
+            ("f.txt:3:age<41>\n".to_string(), true)
+        );
+        // Inverted lines have nothing to replace and print as they are
+        assert_eq!(grep(&["-E", "-v", "--replace", "X", "="], input), ("skip\n".to_string(), true));
+    }
+
+    #[test]
+    fn only_matching_prints_each_expanded_match() {
+        let input = "a=1 b=2\nnone\nc=3\n";
+        assert_eq!(
+            grep(&["-E", "-o", "--replace", "<$1>", "(\\w)=\\d"], input),
+            ("<a>\n<b>\n<c>\n".to_string(), true)
+        );
+        assert_eq!(
+            grep(&["-E", "-o", "-n", "--output-separator=,", "--replace", "$2$1", "(\\w)=(\\d)", "f.txt"], input),
+            ("f.txt:1:1a,2b\nf.txt:3:3c\n".to_string(), true)
+        );
+        assert_eq!(grep(&["-E", "--unique=count", "--replace", "${1}", "(\\w)=\\d"], "a=1 a=2 b=3\n").0, "      2 a\n      1 b\n");
+        // Zero-width matches are still skipped, even if the template has text
+        assert_eq!(grep(&["-E", "-o", "--replace", ">", "^"], "ab\n"), (String::new(), true));
+    }
+
+    #[test]
+    fn backreference_to_an_unset_group_under_only_matching() {
+        assert_eq!(grep(&["-E", "(a)?\\1b"], "b\nc\n"), ("b\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-o", "(a)?\\1b"], "b ab aab\n"), ("b\nb\naab\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-o", "--replace", "[$1]", "(a)?\\1b"], "b aab\n"), ("[]\n[a]\n".to_string(), true));
+    }
+
+    #[test]
+    fn stdin_is_searched_past_its_first_line() {
+        // As `printf 'a\nb\n' | grep -E .` would read it: unlabelled, line by line
+        assert_eq!(grep(&["-E", "."], "a\nb\n"), ("a\nb\n".to_string(), true));
+        assert_eq!(grep(&["-E", "c"], "a\nb\nc\n"), ("c\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-n", "b|d"], "a\nb\nc\nd"), ("2:b\n4:d\n".to_string(), true));
+        assert_eq!(grep(&["-E", "z"], "a\nb\n"), (String::new(), false));
+    }
+
+    #[test]
+    fn empty_lines_and_a_missing_final_newline() {
+        let input = "a\n\nb\n\n";
+        assert_eq!(grep(&["-E", "^$"], input), ("\n\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-n", "^$"], input), ("2:\n4:\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-c", "^$"], input), ("2\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-v", "-n", "^$"], input), ("1:a\n3:b\n".to_string(), true));
+        // The last line counts even without a newline, and gains one when printed
+        assert_eq!(grep(&["-E", "-n", "b"], "a\nb"), ("2:b\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-c", "^$"], "a\nb"), ("0\n".to_string(), false));
+        assert_eq!(grep(&["-E", "b$"], "ab\r\n"), ("ab\n".to_string(), true));
+        // Empty input has no lines at all, not one empty line
+        assert_eq!(grep(&["-E", "^$"], ""), (String::new(), false));
+        assert_eq!(grep(&["-E", "-c", ""], ""), ("0\n".to_string(), false));
+        assert_eq!(grep(&["-E", "-v", "x"], ""), (String::new(), false));
+    }
+
+    #[test]
+    fn directory_targets_need_r_or_auto_recurse() {
+        let dir = TempDir::new("targets");
+        dir.write("sub/a.txt", "x\n");
+        dir.write("b.txt", "x\n");
+        let root = dir.path();
+        let visits = |target: &Path, flags: &[&str]| {
+            let mut args = vec!["-E", "x"];
+            args.extend(flags);
+            let mut found = Vec::new();
+            visit_target(&target.to_string_lossy(), &parse(&args), &mut |name| {
+                found.push(Path::new(name).strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"));
+                true
+            });
+            found
+        };
+        // A directory is skipped unless asked for
+        assert!(visits(root, &[]).is_empty());
+        for flags in [&["--auto-recurse"][..], &["-r"], &["-r", "--auto-recurse"]] {
+            assert_eq!(visits(root, flags), ["b.txt", "sub/a.txt"], "{:?}", flags);
+        }
+        // A file is searched either way
+        for flags in [&[][..], &["--auto-recurse"], &["-r"]] {
+            assert_eq!(visits(&root.join("b.txt"), flags), ["b.txt"], "{:?}", flags);
+        }
+    }
+
+    #[test]
+    fn recursion_visits_entries_in_name_order() {
+        let dir = TempDir::new("order");
+        for file in ["z.txt", "b.txt", "m/y.txt", "m/a.txt", "a.txt"] {
+            dir.write(file, "x\n");
+        }
+        let root = dir.path();
+        let mut found = Vec::new();
+        walk_files_recursively(root, &parse(&["-r", "-E", "x"]), &mut |name| {
+            found.push(Path::new(name).strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"));
+            true
+        });
+        assert_eq!(found, ["a.txt", "b.txt", "m/a.txt", "m/y.txt", "z.txt"]);
+    }
+
+    #[test]
+    fn recursion_skips_dotfiles_unless_hidden() {
+        let dir = TempDir::new("walk");
+        for file in ["a.txt", ".env", "src/b.rs", "src/.hidden.rs", ".git/HEAD", "src/.cache/c.txt"] {
+            dir.write(file, "x\n");
+        }
+        let root = dir.path();
+        let walk = |args: &[&str]| {
+            let mut found = Vec::new();
+            walk_files_recursively(root, &parse(args), &mut |name| {
+                found.push(Path::new(name).strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"));
+                true
+            });
+            found
+        };
+        assert_eq!(walk(&["-r", "-E", "x"]), ["a.txt", "src/b.rs"]);
+        assert_eq!(
+            walk(&["-r", "--hidden", "-E", "x"]),
+            [".env", ".git/HEAD", "a.txt", "src/.cache/c.txt", "src/.hidden.rs", "src/b.rs"]
+        );
+        // A dot-directory named on the command line is still searched
+        let git = root.join(".git");
+        let mut found = 0;
+        walk_files_recursively(&git, &parse(&["-r", "-E", "x"]), &mut |_| {
+            found += 1;
+            true
+        });
+        assert_eq!(found, 1);
+    }
+
+    #[test]
+    fn trailing_context_stops_at_the_end_of_input() {
+        let input = "a\nb\nhit";
+        assert_eq!(grep(&["-E", "-A", "3", "hit"], input), ("hit\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-A", "3", "-n", "b"], input), ("2:b\n3-hit\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-A", "3", "hit"], "hit\nhit\n"), ("hit\nhit\n".to_string(), true));
+    }
+
+    #[test]
+    fn context_separators_only_come_between_groups() {
+        let files = ["1\nhit\n", "hit\n2\n3\n4\n5\n", "x\nhit\n"];
+        assert_eq!(
+            grep_each(&["-E", "-A", "3", "hit", "f1", "f2", "f3"], &files),
+            ("f1:hit\n--\nf2:hit\nf2-2\nf2-3\nf2-4\n--\nf3:hit\n".to_string(), true)
+        );
+        // Files without a match print nothing, not even a separator
+        assert_eq!(
+            grep_each(&["-E", "-B", "1", "hit", "f1", "f2", "f3"], &["hit\n", "none\n", ""]),
+            ("f1:hit\n".to_string(), true)
+        );
+        assert_eq!(
+            grep_each(&["-E", "-C", "1", "hit", "f1", "f2", "f3"], &["none\n", "a\nhit\n", "nope\n"]),
+            ("f2-a\nf2:hit\n".to_string(), true)
+        );
+        // Without context there are no separators at all
+        assert_eq!(grep_each(&["-E", "hit", "f1", "f2"], &["hit\n", "hit\n"]), ("f1:hit\nf2:hit\n".to_string(), true));
+    }
+
+    #[test]
+    fn overlapping_context_merges_into_one_group() {
+        let lines = "1\n2\nhit\n4\nhit\nhit\n7\n8\n9\n10\n11\nhit\n";
+        // 3 and 5 are one line apart and 5, 6 adjacent, so their context runs
+        // together; 12 is far enough away to start a group of its own
+        assert_eq!(
+            grep(&["-E", "-n", "-C", "2", "hit"], lines),
+            ("1-1\n2-2\n3:hit\n4-4\n5:hit\n6:hit\n7-7\n8-8\n--\n10-10\n11-11\n12:hit\n".to_string(), true)
+        );
+        // Groups that just touch are not split
+        assert_eq!(grep(&["-E", "-C", "1", "hit"], "hit\na\nb\nhit\n"), ("hit\na\nb\nhit\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-C", "1", "hit"], "hit\na\nb\nc\nhit\n"), ("hit\na\n--\nc\nhit\n".to_string(), true));
+    }
+
+    #[test]
+    fn max_matches_total_counts_lines_or_only_matches() {
+        let input = "a a a\nb\na\na\n";
+        assert_eq!(grep(&["-E", "--max-matches-total", "2", "a"], input), ("a a a\na\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-n", "--max-matches-total", "1", "a"], input), ("1:a a a\n".to_string(), true));
+        // Under -o each printed match counts, and the budget can end mid-line
+        assert_eq!(grep(&["-E", "-o", "--max-matches-total", "2", "a"], input), ("a\na\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-o", "--max-matches-total", "4", "a"], input), ("a\na\na\na\n".to_string(), true));
+        assert_eq!(
+            grep(&["-E", "-o", "--output-separator=,", "--max-matches-total", "2", "a"], input),
+            ("a,a\n".to_string(), true)
+        );
+        // The budget is shared by every file
+        assert_eq!(
+            grep_each(&["-E", "-o", "--max-matches-total", "3", "a", "f1", "f2"], &["a a\n", "a a\n"]),
+            ("f1:a\nf1:a\nf2:a\n".to_string(), true)
+        );
+        // -c counts lines, so the budget does too
+        assert_eq!(grep(&["-E", "-c", "-o", "--max-matches-total", "2", "a"], input), ("2\n".to_string(), true));
+    }
+
+    #[test]
+    fn count_only_asks_whether_each_line_matches() {
+        // Finding the span of this match takes more steps than deciding that the
+        // line matches, so a limit in between shows -c never looks for spans
+        let line = "ababababc";
+        let regex = Regex::new("(a|b)*c").unwrap().step_limit(60);
+        assert_eq!(regex.is_match(line), Ok(true));
+        assert_eq!(regex.find(line), Err(MatchError::ComplexityLimit));
+        assert_eq!(grep(&["-E", "-c", "--step-limit", "60", "(a|b)*c"], line), ("1\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-c", "-o", "--step-limit", "60", "(a|b)*c"], line), ("1\n".to_string(), true));
+    }
+
+    #[test]
+    fn zero_width_matches_under_only_matching_and_count() {
+        let input = "baaac\nxyz\naa\n\n";
+        // `a*` matches empty text before `b` and `c`, but only its non-empty matches print
+        assert_eq!(grep(&["-E", "-o", "-n", "a*"], input), ("1:aaa\n3:aa\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-o", "a*$"], input), ("aa\n".to_string(), true));
+        // `^` and `\b` only ever match empty text, so -o prints nothing, though
+        // the lines still match
+        assert_eq!(grep(&["-E", "-o", "^"], input), (String::new(), true));
+        assert_eq!(grep(&["-E", "-o", "\\b"], input), (String::new(), true));
+        // -c counts each matching line once, however many empty matches it has
+        assert_eq!(grep(&["-E", "-c", "a*"], input), ("4\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-c", "^"], input), ("4\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-c", "\\b"], input), ("3\n".to_string(), true));
+        assert_eq!(grep(&["-E", "-c", "-o", "a*"], input), ("4\n".to_string(), true));
+    }
+
+    #[test]
+    fn patterns_from_e_and_f_are_alternatives() {
+        let input = "foo\nbaz\nbar\nquux\n";
+        assert_eq!(grep(&["-E", "-e", "foo", "-e", "bar"], input), ("foo\nbar\n".to_string(), true));
+        let dir = TempDir::new("patterns");
+        let file = dir.write("patterns.txt", "foo\nqu+x\n");
+        let from_file = grep(&["-E", "-e", "bar", "-f", &file.to_string_lossy()], input);
+        assert_eq!(from_file, ("foo\nbar\nquux\n".to_string(), true));
+        // Without -e or -f, the first argument is still the pattern
+        assert_eq!(grep(&["-E", "ba."], input), ("baz\nbar\n".to_string(), true));
+    }
+
+    #[test]
+    fn quiet_prints_nothing_and_stops_at_the_first_selected_line() {
+        let quiet: [&[&str]; 4] =
+            [&["-E", "-q", "a"], &["-E", "-q", "-c", "a"], &["-E", "-q", "-o", "-n", "a"], &["-E", "-q", "-C", "1", "a"]];
+        for args in quiet {
+            assert_eq!(grep(args, "x\na\nb\na\n"), (String::new(), true), "{:?}", args);
+        }
+        // Not even a count of 0 when nothing is selected
+        assert_eq!(grep(&["-E", "-q", "-c", "z"], "x\na\n"), (String::new(), false));
+        assert_eq!(grep(&["-E", "-q", "-v", "a"], "a\nb\n"), (String::new(), true));
+        // The lines after the first selected one are never read
+        let (_, matched) = searching(&["-E", "-q", "a"], |_, output, progress, out| {
+            let mut input = "x\na\nb\na\n".as_bytes();
+            let matched = match_lines(&mut input, None, output, progress, out);
+            assert_eq!(input, b"b\na\n");
+            matched
+        });
+        assert!(matched);
+    }
+
+    #[test]
+    fn stats_count_the_lines_each_pattern_matched() {
+        let input = "error 42\nwarning\nerror 7 timeout\nok\n";
+        let args = ["-E", "--stats", "-c", "-e", "error", "-e", "\\d+", "-e", "timeout", "-e", "panic"];
+        assert_eq!(grep(&args, input), ("2\n      2 error\n      2 \\d+\n      1 timeout\n      0 panic\n".to_string(), true));
+        // Counted whether or not -v selects the line, across every input
+        let (out, _) = grep_each(&["-E", "--stats", "-v", "-c", "-e", "o", "-e", "^w"], &[input, "hi\n"]);
+        assert_eq!(out, "0\n1\n      3 o\n      1 ^w\n");
+    }
+
+    #[test]
+    fn count_matches_counts_each_empty_match_once() {
+        // One empty match before each char and one at the end
+        assert_eq!(grep(&["-E", "--count-matches", "x*"], "abc"), ("4\n".to_string(), true));
+        // Empty at 0, `aaa`, empty at 4 and at the end, then four on `xyz`
+        assert_eq!(grep(&["-E", "--count-matches", "a*"], "baaac\nxyz\n"), ("8\n".to_string(), true));
+        assert_eq!(grep(&["-E", "--count-matches", "^"], "ab\ncd\n"), ("2\n".to_string(), true));
+        assert_eq!(grep(&["-E", "--count-matches", "\\b"], "ab cd\n!\n"), ("4\n".to_string(), true));
+        assert_eq!(grep(&["-E", "--count-matches", "o"], "foo\nbar\nboo\n"), ("4\n".to_string(), true));
+        assert_eq!(grep(&["-E", "--count-matches", "z"], "foo\n"), ("0\n".to_string(), false));
+        // Inverted lines have no matches, so each counts once as with -c
+        assert_eq!(grep(&["-E", "--count-matches", "-v", "o"], "foo\nbar\nbaz\n"), ("2\n".to_string(), true));
+        let (out, _) = grep_each(&["-E", "--count-matches", "o", "a", "b"], &["foo\n", "no\n"]);
+        assert_eq!(out, "a:2\nb:1\n");
+    }
+}
@@ -1,207 +1,1349 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-use crate::parser::{RegexNode, RepeatKind};
+use crate::analysis::find_catastrophic;
+use crate::nfa::Program;
+use crate::parser::{ClassItem, Flags, PosixClass, RegexNode, RepeatKind, SetOp, Shorthand};
+use crate::unicode_tables::{GraphemeBreak, CASE_FOLDING, EXTENDED_PICTOGRAPHIC, GRAPHEME_BREAKS};
 
-// Match a node against input at position `pos`, returning all possible end positions.
+// Outcome of matching a (possibly incomplete) input against a whole pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchResult {
+    // The input as a whole matches the pattern
+    Full,
+    // No match yet, but appending more input could still produce one
+    Partial,
+    // No amount of extra input can make this input match
+    NoMatch,
+}
+
+// Which of the possible matches at the leftmost start position `find` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MatchKind {
+    // POSIX: the longest match, so `(a|ab)` on "ab" matches "ab"
+    LeftmostLongest,
+    // Perl: the first match in preference order, so `(a|ab)` on "ab" matches "a"
+    #[default]
+    LeftmostFirst,
+}
+
+// Engine-wide settings that change what individual nodes match
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MatchOptions {
+    // Restrict `\w`, `\s` and `.` to ASCII instead of any Unicode char (`\d` always is)
+    pub ascii: bool,
+    // Compare literals, classes and backreferences ignoring case (`\d`, `\w` are unaffected)
+    pub case_insensitive: bool,
+    // Let `^` and `$` also match just after and before each `\n`
+    pub multi_line: bool,
+    // Let `.` match `\n` too
+    pub dot_all: bool,
+    // Give up with `MatchError::ComplexityLimit` after this many matcher steps
+    // in one search; None means no limit
+    pub step_limit: Option<usize>,
+}
+
+impl MatchOptions {
+    // These options with the inline flags of a `(?i)`-style group applied
+    pub(crate) fn with_flags(self, flags: Flags) -> MatchOptions {
+        MatchOptions {
+            case_insensitive: flags.case_insensitive.unwrap_or(self.case_insensitive),
+            multi_line: flags.multi_line.unwrap_or(self.multi_line),
+            dot_all: flags.dot_all.unwrap_or(self.dot_all),
+            ascii: flags.unicode.map_or(self.ascii, |unicode| !unicode),
+            ..self
+        }
+    }
+}
+
+// Why a search was abandoned before it could say whether the input matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchError {
+    // The search took more steps than `MatchOptions::step_limit` allows
+    ComplexityLimit,
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchError::ComplexityLimit => write!(f, "match step limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for MatchError {}
+
+// Capture spans recorded along one match path, keyed by group number
+pub type Groups = HashMap<usize, (usize, usize)>;
+
+// Bookkeeping shared by every path of a single match attempt
+#[derive(Debug, Default)]
+pub struct MatchState {
+    // Set when some path failed only because it ran past the end of the input
+    pub hit_end: bool,
+    // With backreferences, two paths at the same position but with different
+    // captures can still diverge later, so they must not be merged
+    pub distinct_groups: bool,
+    // Set when the caller only needs match spans, so groups needn't be recorded
+    // (never with backreferences, which read them back during matching)
+    pub skip_captures: bool,
+    // Matcher steps taken so far, and whether that went over the step limit
+    pub steps: usize,
+    pub limit_hit: bool,
+    pub options: MatchOptions,
+    // Set to remember where each repeat and group can end from each position,
+    // so searching from the next start doesn't explore them all over again
+    pub memoize: bool,
+    // End positions in preference order, keyed by node identity and start position
+    memo: HashMap<(*const RegexNode, usize), Vec<usize>>,
+}
+
+impl MatchState {
+    // Fresh state for matching `ast`
+    pub fn new(ast: &RegexNode, options: MatchOptions) -> Self {
+        MatchState {
+            distinct_groups: has_backreference(ast),
+            options,
+            ..Default::default()
+        }
+    }
+
+    // Fresh state for matching `ast` when only match spans are wanted. Without
+    // captures a node's ends depend only on where it starts, so patterns prone
+    // to catastrophic backtracking (like `(a*)*b`) are memoized: that keeps them
+    // polynomial in the input length. Other patterns skip the memo's memory cost
+    // unless the NFA can't take them (see `find_with`).
+    pub fn spans_only(ast: &RegexNode, options: MatchOptions) -> Self {
+        let mut state = MatchState::new(ast, options);
+        state.skip_captures = !state.distinct_groups;
+        state.memoize = state.skip_captures && find_catastrophic(ast).is_some();
+        state
+    }
+
+    // Turn a tripped step limit into an error once the search has unwound
+    fn check_limit(&self) -> Result<(), MatchError> {
+        if self.limit_hit {
+            Err(MatchError::ComplexityLimit)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Match a node against input at position `pos`, returning every way it can match
+// as an end position plus the captures recorded on that path. Paths are listed in
+// backtracking preference order (greedy repeats and earlier alternation branches
+// first), so the first entry is what a Perl-style engine picks.
 // We use Vec<char> for Unicode-safety; no byte slicing.
 pub fn match_node(
     node: &RegexNode,
     input: &[char],
     pos: usize,
-    groups: &mut HashMap<usize, (usize, usize)>,
-) -> Vec<usize> {
-    match node {
-        RegexNode::Group { group_num, node: inner } => {
-            // Save the start position, match the inner node, and save the end position for each successful match
-            let mut results = Vec::new();
-            let mut local_groups = groups.clone();
-            let ends = match_node(inner, input, pos, &mut local_groups);
-            for end in ends {
-                let mut branch_groups = local_groups.clone();
-                branch_groups.insert(*group_num, (pos, end));
-                // Update the caller's groups only if this path matches
-                for (k, v) in branch_groups.iter() {
-                    groups.insert(*k, *v);
-                }
-                results.push(end);
-            }
-            results
+    groups: &Groups,
+    state: &mut MatchState,
+) -> Vec<(usize, Groups)> {
+    // Past the step limit every node fails, so the search unwinds quickly
+    if let Some(limit) = state.options.step_limit {
+        state.steps += 1;
+        if state.steps > limit {
+            state.limit_hit = true;
         }
-        RegexNode::Literal(c) => {
-            if pos < input.len() && input[pos] == *c {
-                vec![pos + 1]
-            } else {
-                vec![]
-            }
+        if state.limit_hit {
+            return vec![];
         }
-        RegexNode::Dot => {
-            if pos < input.len() {
-                vec![pos + 1]
-            } else {
-                vec![]
-            }
-        }
-        RegexNode::Digit => {
-            if pos < input.len() && input[pos].is_digit(10) {
-                vec![pos + 1]
-            } else {
-                vec![]
-            }
-        }
-        RegexNode::Word => {
-            if pos < input.len() && (input[pos].is_alphanumeric() || input[pos] == '_') {
-                vec![pos + 1]
-            } else {
-                vec![]
-            }
+    }
+    // Every consuming node needs at least one more char; remember when we ran out
+    if pos >= input.len() && consumes_char(node) {
+        state.hit_end = true;
+        return vec![];
+    }
+    // Repeats and groups are what get re-explored from the same position
+    let cacheable = state.memoize && matches!(node, RegexNode::Repeat { .. } | RegexNode::Group { .. });
+    let key = (node as *const RegexNode, pos);
+    if cacheable {
+        if let Some(ends) = state.memo.get(&key) {
+            return ends.iter().map(|&end| (end, Groups::new())).collect();
         }
-        RegexNode::CharClass { chars, negated } => {
-            if pos >= input.len() {
-                return vec![];
-            }
-            let contains = chars.contains(&input[pos]);
-            if (*negated && !contains) || (!*negated && contains) {
-                vec![pos + 1]
+    }
+    let paths = match_uncached(node, input, pos, groups, state);
+    if cacheable {
+        state.memo.insert(key, paths.iter().map(|(end, _)| *end).collect());
+    }
+    paths
+}
+
+// The body of `match_node`, without the step limit and memo checks
+fn match_uncached(
+    node: &RegexNode,
+    input: &[char],
+    pos: usize,
+    groups: &Groups,
+    state: &mut MatchState,
+) -> Vec<(usize, Groups)> {
+    match node {
+        RegexNode::Literal(_)
+        | RegexNode::Dot
+        | RegexNode::Digit { .. }
+        | RegexNode::Word { .. }
+        | RegexNode::Space { .. }
+        | RegexNode::HorizontalSpace { .. }
+        | RegexNode::VerticalSpace { .. }
+        | RegexNode::CharClass { .. } => {
+            if char_matches(node, input[pos], state.options) {
+                vec![(pos + 1, groups.clone())]
             } else {
                 vec![]
             }
         }
-        RegexNode::StartAnchor => {
-            if pos == 0 {
-                vec![pos]
-            } else {
-                vec![]
+        RegexNode::Group { group_num, node: inner } => {
+            // Record the span on each path through the group; paths that fail never record it
+            let mut paths = match_node(inner, input, pos, groups, state);
+            if !state.skip_captures {
+                for (end, path_groups) in paths.iter_mut() {
+                    path_groups.insert(*group_num, (pos, *end));
+                }
             }
+            paths
         }
-        RegexNode::EndAnchor => {
-            if pos == input.len() {
-                vec![pos]
+        RegexNode::WordStart
+        | RegexNode::WordEnd
+        | RegexNode::WordBoundary { .. }
+        | RegexNode::StartAnchor
+        | RegexNode::EndAnchor
+        | RegexNode::InputStart
+        | RegexNode::InputEnd { .. } => {
+            if assertion_holds(node, input, pos, state.options) {
+                vec![(pos, groups.clone())]
             } else {
                 vec![]
             }
         }
         RegexNode::Seq(nodes) => {
-            // Accumulate possible positions as we progress through the sequence
-            let mut positions = vec![pos];
+            // Accumulate possible paths as we progress through the sequence
+            let mut paths = vec![(pos, groups.clone())];
             for n in nodes {
-                let mut next_positions = Vec::new();
-                for p in positions {
-                    let res = match_node(n, input, p, groups);
-                    next_positions.extend(res);
+                let mut next_paths = Vec::new();
+                for (p, path_groups) in &paths {
+                    next_paths.extend(match_node(n, input, *p, path_groups, state));
                 }
-                if next_positions.is_empty() {
+                if next_paths.is_empty() {
                     return vec![];
                 }
-                next_positions.sort_unstable();
-                next_positions.dedup();
-                positions = next_positions;
+                paths = dedup_in_order(next_paths, state);
             }
-            positions
+            paths
         }
         RegexNode::Alt(branches) => {
-            let mut all_positions = Vec::new();
-            let mut all_groups: Vec<HashMap<usize, (usize, usize)>> = Vec::new();
+            // Each branch starts from the caller's captures, so a branch that isn't
+            // taken never leaves its groups behind
+            let mut all_paths = Vec::new();
             for br in branches {
-                let mut branch_groups = groups.clone();
-                let res = match_node(br, input, pos, &mut branch_groups);
-                if !res.is_empty() {
-                    all_positions.extend(res.iter().copied());
-                    all_groups.push(branch_groups);
-                }
+                all_paths.extend(match_node(br, input, pos, groups, state));
+            }
+            dedup_in_order(all_paths, state)
+        }
+        RegexNode::Lookahead { node: inner, negated } => {
+            // Like Perl, only the first way the lookahead matches is kept (with
+            // its captures), and the match continues from where it started
+            let first = match_node(inner, input, pos, groups, state).into_iter().next();
+            match (first, negated) {
+                (Some((_, path_groups)), false) => vec![(pos, path_groups)],
+                (None, true) => vec![(pos, groups.clone())],
+                _ => vec![],
             }
-            all_positions.sort_unstable();
-            all_positions.dedup();
-            // If any branch matched, update groups to the first successful branch
-            if let Some(g) = all_groups.first() {
-                for (k, v) in g.iter() {
-                    groups.insert(*k, *v);
+        }
+        RegexNode::Lookbehind { node: inner, negated } => {
+            // Try each start the inner node's width allows, nearest first, for a
+            // match that ends exactly here. Fixed-width bodies have just one.
+            let (min, max) = width(inner);
+            let earliest = max.map_or(0, |max| pos.saturating_sub(max));
+            let mut first = None;
+            if min <= pos {
+                for start in (earliest..=pos - min).rev() {
+                    let paths = match_node(inner, input, start, groups, state);
+                    first = paths.into_iter().find(|(end, _)| *end == pos);
+                    if first.is_some() {
+                        break;
+                    }
                 }
             }
-            all_positions
+            match (first, negated) {
+                (Some((_, path_groups)), false) => vec![(pos, path_groups)],
+                (None, true) => vec![(pos, groups.clone())],
+                _ => vec![],
+            }
+        }
+        RegexNode::Scoped { node: inner, flags } => {
+            let outer = state.options;
+            state.options = outer.with_flags(*flags);
+            let paths = match_node(inner, input, pos, groups, state);
+            state.options = outer;
+            paths
+        }
+        RegexNode::Grapheme => {
+            if pos >= input.len() {
+                state.hit_end = true;
+                return vec![];
+            }
+            // A cluster that runs to the end of the input could go on in more of it
+            let end = grapheme_end(input, pos);
+            if end == input.len() {
+                state.hit_end = true;
+            }
+            vec![(end, groups.clone())]
         }
+        // The first path is the one a backtracking engine would commit to
+        RegexNode::Atomic(inner) => match_node(inner, input, pos, groups, state).into_iter().take(1).collect(),
         RegexNode::Backreference(n) => {
             if let Some((start, end)) = groups.get(n) {
                 let length = end - start;
-                if pos + length <= input.len() && &input[*start..*end] == &input[pos..pos + length] {
-                    vec![pos + length]
+                let same = |a: &[char], b: &[char]| {
+                    a.iter().zip(b).all(|(x, y)| same_char(*x, *y, state.options))
+                };
+                if pos + length <= input.len() && same(&input[*start..*end], &input[pos..pos + length]) {
+                    vec![(pos + length, groups.clone())]
                 } else {
+                    // Running out of input part-way through the captured text can still be completed
+                    if pos + length > input.len() && same(&input[pos..], &input[*start..*start + input.len() - pos]) {
+                        state.hit_end = true;
+                    }
                     vec![]
                 }
             } else {
-                vec![]
+                // The group didn't take part in the match (e.g. the skipped `(a)?` in
+                // `(a)?\1b`), so it refers to nothing and matches the empty string
+                vec![(pos, groups.clone())]
             }
         }
-        RegexNode::Repeat { node: inner, kind } => match kind {
+        RegexNode::Repeat { node: inner, kind, greedy } => match kind {
             RepeatKind::ZeroOrOne => {
-                // Either skip it or take one
-                let mut positions = vec![pos];
-                positions.extend(match_node(inner, input, pos, groups));
-                positions.sort_unstable();
-                positions.dedup();
-                positions
-            }
-            RepeatKind::OneOrMore => {
-                // Keep applying `inner` as long as we can, collecting all positions
-                let mut results = Vec::new();
-                let mut frontier = match_node(inner, input, pos, groups);
-                while !frontier.is_empty() {
-                    for p in &frontier {
-                        if !results.contains(p) {
-                            results.push(*p);
-                        }
-                    }
-                    // Advance one more repetition from each frontier point
-                    let mut next = Vec::new();
-                    for p in &frontier {
-                        let step = match_node(inner, input, *p, groups);
-                        next.extend(step);
-                    }
-                    next.sort_unstable();
-                    next.dedup();
-                    frontier = next;
+                // Prefer taking one, then fall back to skipping it (the other way
+                // round when lazy)
+                let mut paths = match_node(inner, input, pos, groups, state);
+                if *greedy {
+                    paths.push((pos, groups.clone()));
+                } else {
+                    paths.insert(0, (pos, groups.clone()));
                 }
-                results.sort_unstable();
-                results.dedup();
-                results
-            }
-            RepeatKind::ZeroOrMore => {
-                // Keep the current position as a valid match (zero occurrences)
-                let mut results = vec![pos];
-                // First occurrence
-                let mut frontier = match_node(inner, input, pos, groups);
-                while !frontier.is_empty() {
-                    for p in &frontier {
-                        if !results.contains(&p) {
-                            results.push(*p);
-                        }
-                    }
-                    let mut next: Vec<usize> = Vec::new();
-                    for p in &frontier {
-                        let step = match_node(inner, input, *p, groups);
-                        next.extend(step);
-                    }
-                    next.sort_unstable();
-                    next.dedup();
-                    frontier = next;
+                dedup_in_order(paths, state)
+            }
+            RepeatKind::OneOrMore => match_repeat(inner, input, pos, (1, None), *greedy, groups, state),
+            RepeatKind::ZeroOrMore => match_repeat(inner, input, pos, (0, None), *greedy, groups, state),
+            RepeatKind::Bounded { min, max } => match_repeat(inner, input, pos, (*min, *max), *greedy, groups, state),
+        },
+    }
+}
+
+// Apply `inner` at least `min` and at most `max` (if any) times, collecting all
+// paths reachable from `pos`. Results are in preference order: more repetitions
+// first, then fewer, or the other way round when not `greedy`.
+fn match_repeat(
+    inner: &RegexNode,
+    input: &[char],
+    pos: usize,
+    (min, max): (usize, Option<usize>),
+    greedy: bool,
+    groups: &Groups,
+    state: &mut MatchState,
+) -> Vec<(usize, Groups)> {
+    let start = (pos, groups.clone());
+    // levels[k] holds the paths first reached after k repetitions
+    let mut levels = vec![vec![start.clone()]];
+    // Once `min` is satisfied, revisiting a path can't add anything new
+    // (this also stops zero-width repetitions like `(a*)*` from looping forever)
+    let mut seen = SeenPaths::new(state);
+    if min == 0 {
+        seen.insert(&start, state);
+    }
+    let mut frontier = vec![start];
+    while !frontier.is_empty() {
+        // levels.len() - 1 repetitions have been applied so far
+        if let Some(max) = max {
+            if levels.len() > max {
+                break;
+            }
+        }
+        let count = levels.len();
+        let mut next = Vec::new();
+        for (p, path_groups) in &frontier {
+            for path in match_node(inner, input, *p, path_groups, state) {
+                let revisit = seen.contains(&path, state);
+                if count > min && revisit {
+                    continue;
+                }
+                if count >= min && !revisit {
+                    seen.insert(&path, state);
                 }
-                results.sort_unstable();
-                results.dedup();
-                results
+                next.push(path);
             }
+        }
+        let next = dedup_in_order(next, state);
+        levels.push(next.clone());
+        frontier = next;
+    }
+    let levels = levels.into_iter().skip(min);
+    let paths = if greedy {
+        levels.rev().flatten().collect()
+    } else {
+        levels.flatten().collect()
+    };
+    dedup_in_order(paths, state)
+}
+
+// Two paths are interchangeable if they end at the same position and, when
+// backreferences could tell them apart, also hold the same captures
+fn same_path(a: &(usize, Groups), b: &(usize, Groups), state: &MatchState) -> bool {
+    a.0 == b.0 && (!state.distinct_groups || a.1 == b.1)
+}
+
+// Drop repeated paths, keeping the first (most preferred) occurrence of each
+fn dedup_in_order(paths: Vec<(usize, Groups)>, state: &MatchState) -> Vec<(usize, Groups)> {
+    let mut seen = SeenPaths::new(state);
+    paths.into_iter().filter(|path| seen.insert(path, state)).collect()
+}
+
+// A set of paths compared with `same_path`. When captures can't tell paths
+// apart, only the end position matters and a hash set keeps lookups cheap.
+enum SeenPaths {
+    Ends(HashSet<usize>),
+    Paths(Vec<(usize, Groups)>),
+}
+
+impl SeenPaths {
+    fn new(state: &MatchState) -> Self {
+        if state.distinct_groups {
+            SeenPaths::Paths(Vec::new())
+        } else {
+            SeenPaths::Ends(HashSet::new())
+        }
+    }
+
+    fn contains(&self, path: &(usize, Groups), state: &MatchState) -> bool {
+        match self {
+            SeenPaths::Ends(ends) => ends.contains(&path.0),
+            SeenPaths::Paths(paths) => paths.iter().any(|p| same_path(p, path, state)),
+        }
+    }
+
+    // Add the path, returning false if an equivalent one was already there
+    fn insert(&mut self, path: &(usize, Groups), state: &MatchState) -> bool {
+        if self.contains(path, state) {
+            return false;
+        }
+        match self {
+            SeenPaths::Ends(ends) => {
+                ends.insert(path.0);
+            }
+            SeenPaths::Paths(paths) => paths.push(path.clone()),
+        }
+        true
+    }
+}
+
+// Does the AST contain a backreference anywhere?
+fn has_backreference(node: &RegexNode) -> bool {
+    match node {
+        RegexNode::Backreference(_) => true,
+        RegexNode::Seq(nodes) | RegexNode::Alt(nodes) => nodes.iter().any(has_backreference),
+        RegexNode::Repeat { node, .. }
+        | RegexNode::Group { node, .. }
+        | RegexNode::Lookahead { node, .. }
+        | RegexNode::Lookbehind { node, .. }
+        | RegexNode::Atomic(node)
+        | RegexNode::Scoped { node, .. } => has_backreference(node),
+        _ => false,
+    }
+}
+
+// The fewest and most chars a match of `node` can span (None for no limit)
+pub(crate) fn width(node: &RegexNode) -> (usize, Option<usize>) {
+    match node {
+        RegexNode::Seq(nodes) => nodes.iter().map(width).fold((0, Some(0)), |(min, max), (n_min, n_max)| {
+            (min + n_min, max.zip(n_max).map(|(a, b)| a + b))
+        }),
+        RegexNode::Alt(branches) if branches.is_empty() => (0, Some(0)),
+        RegexNode::Alt(branches) => branches.iter().map(width).fold((usize::MAX, Some(0)), |(min, max), (b_min, b_max)| {
+            (min.min(b_min), max.zip(b_max).map(|(a, b)| a.max(b)))
+        }),
+        RegexNode::Repeat { node: inner, kind, .. } => {
+            let (min, max) = match *kind {
+                RepeatKind::ZeroOrOne => (0, Some(1)),
+                RepeatKind::OneOrMore => (1, None),
+                RepeatKind::ZeroOrMore => (0, None),
+                RepeatKind::Bounded { min, max } => (min, max),
+            };
+            let (inner_min, inner_max) = width(inner);
+            let most = match (max, inner_max) {
+                (_, Some(0)) | (Some(0), _) => Some(0),
+                (Some(max), Some(inner_max)) => max.checked_mul(inner_max),
+                _ => None,
+            };
+            (min.saturating_mul(inner_min), most)
+        }
+        RegexNode::Group { node: inner, .. } | RegexNode::Atomic(inner) | RegexNode::Scoped { node: inner, .. } => {
+            width(inner)
+        }
+        // A backreference can be as long as any captured text
+        RegexNode::Backreference(_) => (0, None),
+        RegexNode::Grapheme => (1, None),
+        _ if consumes_char(node) => (1, Some(1)),
+        _ => (0, Some(0)),
+    }
+}
+
+// Nodes that always consume exactly one input char when they match
+pub(crate) fn consumes_char(node: &RegexNode) -> bool {
+    matches!(
+        node,
+        RegexNode::Literal(_)
+            | RegexNode::Dot
+            | RegexNode::Digit { .. }
+            | RegexNode::Word { .. }
+            | RegexNode::Space { .. }
+            | RegexNode::HorizontalSpace { .. }
+            | RegexNode::VerticalSpace { .. }
+            | RegexNode::CharClass { .. }
+    )
+}
+
+// Does the single-char node (one that `consumes_char`) match `c`?
+pub(crate) fn char_matches(node: &RegexNode, c: char, options: MatchOptions) -> bool {
+    match node {
+        RegexNode::Literal(l) => same_char(c, *l, options),
+        RegexNode::Dot => (!options.ascii || c.is_ascii()) && (options.dot_all || c != '\n'),
+        // Only ASCII digits count, in either mode
+        RegexNode::Digit { negated } => c.is_ascii_digit() != *negated,
+        RegexNode::Word { negated } => is_word_char(c, options) != *negated,
+        RegexNode::Space { negated } => is_space_char(c, options) != *negated,
+        RegexNode::HorizontalSpace { negated } => is_horizontal_space(c) != *negated,
+        RegexNode::VerticalSpace { negated } => is_vertical_space(c) != *negated,
+        RegexNode::CharClass { items, negated } => {
+            let mut contains = items.iter().any(|item| class_item_contains(item, c, options));
+            if !contains && options.case_insensitive {
+                // Try the other cases of `c`, so `[a-z]` takes `Q` and `[A-Z]` takes `q`
+                contains = case_variants(c)
+                    .iter()
+                    .any(|&variant| items.iter().any(|item| class_item_contains(item, variant, options)));
+            }
+            contains != *negated
+        }
+        _ => false,
+    }
+}
+
+// Does the zero-width assertion (an anchor or word boundary) hold at `pos`?
+pub(crate) fn assertion_holds(node: &RegexNode, input: &[char], pos: usize, options: MatchOptions) -> bool {
+    // Either side may be past the ends of the input, which counts as a non-word char
+    let before = || pos > 0 && is_word_char(input[pos - 1], options);
+    let after = || pos < input.len() && is_word_char(input[pos], options);
+    match node {
+        RegexNode::StartAnchor => pos == 0 || (options.multi_line && input[pos - 1] == '\n'),
+        RegexNode::EndAnchor => pos == input.len() || (options.multi_line && input[pos] == '\n'),
+        RegexNode::InputStart => pos == 0,
+        RegexNode::InputEnd { before_newline } => {
+            pos == input.len() || (*before_newline && pos + 1 == input.len() && input[pos] == '\n')
+        }
+        RegexNode::WordStart => !before() && after(),
+        RegexNode::WordEnd => before() && !after(),
+        RegexNode::WordBoundary { negated } => (before() != after()) != *negated,
+        _ => false,
+    }
+}
+
+// Are two chars equal, ignoring case if the options say so? Case is compared by
+// Unicode simple case folding, so `σ`, `ς` and `Σ` are all the same
+fn same_char(a: char, b: char, options: MatchOptions) -> bool {
+    if a == b || !options.case_insensitive {
+        a == b
+    } else if a.is_ascii() && b.is_ascii() {
+        a.eq_ignore_ascii_case(&b)
+    } else {
+        case_variants(a).contains(&b)
+    }
+}
+
+// The other chars that fold to the same char as `c` (none for most chars)
+fn case_variants(c: char) -> &'static [char] {
+    match CASE_FOLDING.binary_search_by(|(first, _)| first.cmp(&c)) {
+        Ok(i) => CASE_FOLDING[i].1,
+        Err(_) => &[],
+    }
+}
+
+// The char that `c` and all its other cases fold to, so that comparing folded
+// chars is the same as comparing with `same_char` ignoring case
+fn fold_case(c: char) -> char {
+    case_variants(c).iter().copied().fold(c, char::min)
+}
+
+// The chars of `node` if it is nothing but literal chars (like `foo` or the
+// empty pattern), which a plain substring search can stand in for
+pub(crate) fn literal_text(node: &RegexNode) -> Option<Vec<char>> {
+    match node {
+        RegexNode::Literal(c) => Some(vec![*c]),
+        RegexNode::Seq(nodes) => nodes
+            .iter()
+            .map(|n| match n {
+                RegexNode::Literal(c) => Some(*c),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+// Does `needle` occur anywhere in `input`? Ignoring case, both are folded once
+// up front and then compared char by char, giving the same answer as matching
+// the literal pattern with the matcher. Each char compared counts as one step
+// towards `options.step_limit`.
+pub(crate) fn contains_literal(input: &[char], needle: &[char], options: MatchOptions) -> Result<bool, MatchError> {
+    if needle.is_empty() {
+        return Ok(true);
+    }
+    let fold = |chars: &[char]| -> Vec<char> { chars.iter().map(|&c| fold_case(c)).collect() };
+    let (input, needle) = if options.case_insensitive {
+        (Cow::Owned(fold(input)), Cow::Owned(fold(needle)))
+    } else {
+        (Cow::Borrowed(input), Cow::Borrowed(needle))
+    };
+    let mut steps = 0;
+    for window in input.windows(needle.len()) {
+        // The chars that agree, plus the one that didn't, if any
+        let agreed = window.iter().zip(needle.iter()).take_while(|(a, b)| a == b).count();
+        steps += (agreed + 1).min(needle.len());
+        if options.step_limit.is_some_and(|limit| steps > limit) {
+            return Err(MatchError::ComplexityLimit);
+        }
+        if agreed == needle.len() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Is `c` a member of one character class item?
+fn class_item_contains(item: &ClassItem, c: char, options: MatchOptions) -> bool {
+    let any = |items: &[ClassItem]| items.iter().any(|item| class_item_contains(item, c, options));
+    match *item {
+        ClassItem::Single(ch) => ch == c,
+        ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+        ClassItem::Shorthand { set, negated } => {
+            let member = match set {
+                Shorthand::Digit => c.is_ascii_digit(),
+                Shorthand::Word => is_word_char(c, options),
+                Shorthand::Space => is_space_char(c, options),
+                Shorthand::HorizontalSpace => is_horizontal_space(c),
+                Shorthand::VerticalSpace => is_vertical_space(c),
+            };
+            member != negated
+        }
+        ClassItem::Posix { class, negated } => posix_class_contains(class, c, options) != negated,
+        ClassItem::Unicode { ranges, negated } => in_ranges(ranges, c) != negated,
+        ClassItem::Class { ref items, negated } => any(items) != negated,
+        ClassItem::SetOp { op, ref left, ref right } => match op {
+            SetOp::Intersection => any(left) && any(right),
+            SetOp::Difference => any(left) && !any(right),
         },
     }
 }
 
-// Try to match at any position (unless ^/$ constrain it via the AST itself)
+// Is `c` in the POSIX class? Letters and spaces follow Unicode unless in ascii
+// mode; digits, hex digits and punctuation are always ASCII.
+fn posix_class_contains(class: PosixClass, c: char, options: MatchOptions) -> bool {
+    let unicode = !options.ascii || c.is_ascii();
+    match class {
+        PosixClass::Alnum => unicode && c.is_alphanumeric(),
+        PosixClass::Alpha => unicode && c.is_alphabetic(),
+        PosixClass::Blank => unicode && is_horizontal_space(c),
+        PosixClass::Cntrl => c.is_control(),
+        PosixClass::Digit => c.is_ascii_digit(),
+        PosixClass::Graph => unicode && !c.is_control() && !c.is_whitespace(),
+        PosixClass::Lower => unicode && c.is_lowercase(),
+        PosixClass::Print => unicode && !c.is_control(),
+        PosixClass::Punct => c.is_ascii_punctuation(),
+        PosixClass::Space => unicode && c.is_whitespace(),
+        PosixClass::Upper => unicode && c.is_uppercase(),
+        PosixClass::Word => is_word_char(c, options),
+        PosixClass::Xdigit => c.is_ascii_hexdigit(),
+    }
+}
+
+// `\w`: letters, digits and `_`, restricted to ASCII in ascii mode
+fn is_word_char(c: char, options: MatchOptions) -> bool {
+    let alphanumeric = if options.ascii {
+        c.is_ascii_alphanumeric()
+    } else {
+        c.is_alphanumeric()
+    };
+    alphanumeric || c == '_'
+}
+
+// `\s`: Unicode whitespace, or only the ASCII space, tab, line breaks, vertical
+// tab and form feed in ascii mode
+fn is_space_char(c: char, options: MatchOptions) -> bool {
+    if options.ascii {
+        matches!(c, ' ' | '\t' | '\n' | '\u{b}' | '\u{c}' | '\r')
+    } else {
+        c.is_whitespace()
+    }
+}
+
+// Is `c` in one of the sorted, non-overlapping inclusive `ranges`?
+fn in_ranges(ranges: &[(char, char)], c: char) -> bool {
+    ranges.binary_search_by(|&(lo, hi)| range_order(lo, hi, c)).is_ok()
+}
+
+// Where the range `lo..=hi` lies relative to `c`
+fn range_order(lo: char, hi: char, c: char) -> Ordering {
+    if hi < c {
+        Ordering::Less
+    } else if lo > c {
+        Ordering::Greater
+    } else {
+        Ordering::Equal
+    }
+}
+
+fn grapheme_break(c: char) -> GraphemeBreak {
+    match GRAPHEME_BREAKS.binary_search_by(|&(lo, hi, _)| range_order(lo, hi, c)) {
+        Ok(i) => GRAPHEME_BREAKS[i].2,
+        Err(_) => GraphemeBreak::Other,
+    }
+}
+
+// `\X`: the end of the extended grapheme cluster starting at `start`, by the
+// rules of UAX #29 (Unicode text segmentation)
+fn grapheme_end(input: &[char], start: usize) -> usize {
+    use GraphemeBreak::*;
+    let mut prev = grapheme_break(input[start]);
+    // An odd number of regional indicators so far, which the next one pairs with
+    let mut odd_flags = prev == RegionalIndicator;
+    // An emoji followed only by extenders, and then that plus a zero width joiner
+    let mut emoji = in_ranges(EXTENDED_PICTOGRAPHIC, input[start]);
+    let mut emoji_zwj = false;
+    let mut end = start + 1;
+    while end < input.len() {
+        let c = input[end];
+        let next = grapheme_break(c);
+        let pictographic = in_ranges(EXTENDED_PICTOGRAPHIC, c);
+        let joins = match (prev, next) {
+            (Cr, Lf) => true,
+            (Control | Cr | Lf, _) | (_, Control | Cr | Lf) => false,
+            // Hangul syllable sequences
+            (L, L | V | Lv | Lvt) | (Lv | V, V | T) | (Lvt | T, T) => true,
+            (_, Extend | Zwj | SpacingMark) | (Prepend, _) => true,
+            (Zwj, _) => emoji_zwj && pictographic,
+            (RegionalIndicator, RegionalIndicator) => odd_flags,
+            _ => false,
+        };
+        if !joins {
+            break;
+        }
+        emoji_zwj = emoji && next == Zwj;
+        emoji = pictographic || (emoji && next == Extend);
+        odd_flags = next == RegionalIndicator && prev != RegionalIndicator;
+        prev = next;
+        end += 1;
+    }
+    end
+}
+
+// `\h`: tab, space and the Unicode space separators
+fn is_horizontal_space(c: char) -> bool {
+    matches!(
+        c,
+        '\t' | ' '
+            | '\u{a0}'
+            | '\u{1680}'
+            | '\u{180e}'
+            | '\u{2000}'..='\u{200a}'
+            | '\u{202f}'
+            | '\u{205f}'
+            | '\u{3000}'
+    )
+}
+
+// `\v`: line feed, vertical tab, form feed, carriage return, NEL and the Unicode line/paragraph separators
+fn is_vertical_space(c: char) -> bool {
+    matches!(c, '\n' | '\u{b}' | '\u{c}' | '\r' | '\u{85}' | '\u{2028}' | '\u{2029}')
+}
+
+// Search for the AST anywhere in the input (unless ^/$ constrain it via the AST itself).
+// This compiles the AST afresh; `Regex` keeps its compiled program instead.
+pub fn search(ast: &RegexNode, input: &[char], options: MatchOptions) -> Result<bool, MatchError> {
+    search_with(ast, Program::compile(ast).as_ref(), input, options)
+}
+
+// Like `search`, with `program` already compiled from `ast` (None if it can't be)
+pub(crate) fn search_with(
+    ast: &RegexNode,
+    program: Option<&Program>,
+    input: &[char],
+    options: MatchOptions,
+) -> Result<bool, MatchError> {
+    // Without backreferences the NFA can answer, in linear time
+    if let Some(program) = program {
+        return program.is_match(input, options);
+    }
+    // Without the NFA, memoizing keeps atomic groups from taking exponential time
+    let mut state = MatchState::spans_only(ast, options);
+    state.memoize = state.skip_captures;
+    for start in 0..=input.len() {
+        if !match_node(ast, input, start, &Groups::new(), &mut state).is_empty() {
+            return Ok(true);
+        }
+        state.check_limit()?;
+    }
+    Ok(false)
+}
+
+// Find the leftmost match of the AST, returning its (start, end) char offsets.
+// `kind` decides which end wins when several are possible at that start.
+pub fn find(
+    ast: &RegexNode,
+    input: &[char],
+    kind: MatchKind,
+    options: MatchOptions,
+) -> Result<Option<(usize, usize)>, MatchError> {
+    find_span_at(ast, input, 0, kind, options)
+}
+
+// Like `find`, but only considers matches starting at or after `from`. Groups
+// aren't recorded, which saves cloning them on every path.
+pub fn find_span_at(
+    ast: &RegexNode,
+    input: &[char],
+    from: usize,
+    kind: MatchKind,
+    options: MatchOptions,
+) -> Result<Option<(usize, usize)>, MatchError> {
+    let mut state = MatchState::spans_only(ast, options);
+    let found = find_with(ast, Program::compile(ast).as_ref(), input, from, kind, &mut state)?;
+    Ok(found.map(|(start, end, _)| (start, end)))
+}
+
+// Like `find_span_at`, but also returns the captures recorded on the path that
+// produced the match.
+pub fn find_at(
+    ast: &RegexNode,
+    input: &[char],
+    from: usize,
+    kind: MatchKind,
+    options: MatchOptions,
+) -> Result<Option<(usize, usize, Groups)>, MatchError> {
+    let mut state = MatchState::new(ast, options);
+    find_with(ast, Program::compile(ast).as_ref(), input, from, kind, &mut state)
+}
+
+// The leftmost match at or after `from`, with `program` already compiled from
+// `ast` (None if it can't be), so searching many lines compiles it only once.
+// Captures are recorded unless `state` skips them.
+pub(crate) fn find_with(
+    ast: &RegexNode,
+    program: Option<&Program>,
+    input: &[char],
+    from: usize,
+    kind: MatchKind,
+    state: &mut MatchState,
+) -> Result<Option<(usize, usize, Groups)>, MatchError> {
+    // Without backreferences the NFA finds where the leftmost match starts, so
+    // only that start needs backtracking to pick the end and captures
+    let starts = match program {
+        Some(program) => match program.leftmost_start(input, from, state.options)? {
+            Some(start) => start..=start,
+            None => return Ok(None),
+        },
+        // Otherwise every start is tried. Without captures that's memoized, so
+        // atomic groups can't make it exponential...
+        None if state.skip_captures => {
+            state.memoize = true;
+            from..=input.len()
+        }
+        // ...and when captures are wanted (but not read back by a backreference),
+        // a memoized pass finds the start first
+        None if !state.distinct_groups => {
+            let mut spans = MatchState::spans_only(ast, state.options);
+            match find_with(ast, None, input, from, kind, &mut spans)? {
+                Some((start, _, _)) => start..=start,
+                None => return Ok(None),
+            }
+        }
+        None => from..=input.len(),
+    };
+    for start in starts {
+        let paths = match_node(ast, input, start, &Groups::new(), state);
+        // A search cut short may have missed a more preferred path at this start
+        state.check_limit()?;
+        if let Some((end, groups)) = select(paths, kind) {
+            return Ok(Some((start, end, groups)));
+        }
+    }
+    Ok(None)
+}
+
+// Match the AST starting exactly at `start` (no searching forward), returning the
+// end of the chosen match and its captures. Tokenizers that already know where
+// the next token begins use this to skip the scan over every start position.
+pub fn match_at(
+    ast: &RegexNode,
+    input: &[char],
+    start: usize,
+    kind: MatchKind,
+    options: MatchOptions,
+) -> Result<Option<(usize, Groups)>, MatchError> {
+    if start > input.len() {
+        return Ok(None);
+    }
+    let mut state = MatchState::new(ast, options);
+    let paths = match_node(ast, input, start, &Groups::new(), &mut state);
+    state.check_limit()?;
+    Ok(select(paths, kind))
+}
+
+// A parsed pattern together with its NFA, compiled once for matching many inputs
+pub(crate) struct Compiled {
+    pub(crate) ast: RegexNode,
+    pub(crate) program: Option<Program>,
+}
+
+impl Compiled {
+    pub(crate) fn new(ast: RegexNode) -> Compiled {
+        Compiled { program: Program::compile(&ast), ast }
+    }
+}
+
+// Pick the reported path out of all the ways a match can end at one start position
+fn select(paths: Vec<(usize, Groups)>, kind: MatchKind) -> Option<(usize, Groups)> {
+    match kind {
+        MatchKind::LeftmostFirst => paths.into_iter().next(),
+        // `max_by_key` keeps the last maximum; we want the most preferred one
+        MatchKind::LeftmostLongest => paths.into_iter().rev().max_by_key(|(end, _)| *end),
+    }
+}
+
+// Match the whole input against the AST, reporting whether a shorter-than-needed
+// input could still be completed. Used to validate data that arrives in chunks.
+pub fn partial_match(ast: &RegexNode, input: &[char], options: MatchOptions) -> Result<MatchResult, MatchError> {
+    let mut state = MatchState::new(ast, options);
+    let paths = match_node(ast, input, 0, &Groups::new(), &mut state);
+    state.check_limit()?;
+    Ok(if paths.iter().any(|(end, _)| *end == input.len()) {
+        MatchResult::Full
+    } else if state.hit_end {
+        MatchResult::Partial
+    } else {
+        MatchResult::NoMatch
+    })
+}
+
+// Try to match at any position (unless ^/$ constrain it via the AST itself).
+// An invalid pattern never matches; callers that need the parse error, or a
+// step limit, should build a `Regex` instead. With the `pattern-cache` feature, repeated
+// calls with the same pattern reuse the parsed AST instead of reparsing it.
 pub fn match_pattern(input_line: &str, pattern: &str) -> bool {
-    let mut parser = crate::parser::Parser::new(pattern);
-    let ast = parser.parse();
+    #[cfg(feature = "pattern-cache")]
+    let parsed = crate::cache::parse_cached(pattern);
+    #[cfg(not(feature = "pattern-cache"))]
+    let parsed = crate::parser::Parser::new(pattern).parse().map(Compiled::new);
+    let ast = match parsed {
+        Ok(ast) => ast,
+        Err(_) => return false,
+    };
     let input_chars: Vec<char> = input_line.chars().collect();
-    for start in 0..=input_chars.len() {
-        let mut groups: HashMap<usize, (usize, usize)> = HashMap::new();
-        if !match_node(&ast, &input_chars, start, &mut groups).is_empty() {
-            return true;
+    // The default options have no step limit, so the search can't fail
+    search_with(&ast.ast, ast.program.as_ref(), &input_chars, MatchOptions::default()) == Ok(true)
+}
+
+// Every line of an in-memory, possibly multi-line string that matches the pattern,
+// as (1-based line number, line) pairs. Lines are split like the CLI splits files:
+// `\n` or `\r\n` endings are dropped and a last line without one still counts.
+// Unlike `match_pattern`, an invalid pattern is an error, with the parser's message.
+pub fn grep_str<'h>(haystack: &'h str, pattern: &str) -> Result<Vec<(usize, &'h str)>, String> {
+    let ast = crate::parser::Parser::new(pattern).parse()?;
+    let program = Program::compile(&ast);
+    Ok(haystack
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let input_chars: Vec<char> = line.chars().collect();
+            // The default options have no step limit, so the search can't fail
+            search_with(&ast, program.as_ref(), &input_chars, MatchOptions::default()) == Ok(true)
+        })
+        .map(|(i, line)| (i + 1, line))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn parse(pattern: &str) -> RegexNode {
+        Parser::new(pattern).parse().unwrap()
+    }
+
+    fn a_run(n: usize) -> Vec<char> {
+        vec!['a'; n]
+    }
+
+    // The text of the leftmost match, if any
+    fn first(pattern: &str, text: &str) -> Option<String> {
+        let input: Vec<char> = text.chars().collect();
+        find(&parse(pattern), &input, MatchKind::LeftmostFirst, MatchOptions::default())
+            .unwrap()
+            .map(|(start, end)| input[start..end].iter().collect())
+    }
+
+    #[test]
+    fn grep_str_splits_lines_like_the_cli() {
+        assert_eq!(grep_str("foo\nbar\nbaz", "^ba"), Ok(vec![(2, "bar"), (3, "baz")]));
+        // `\r\n` endings are dropped, so `$` sits before the `\r`
+        assert_eq!(grep_str("one\r\ntwo\r\n", "o$"), Ok(vec![(2, "two")]));
+        // A last line without a newline still counts; a final newline adds no empty line
+        assert_eq!(grep_str("a\nb", "b"), Ok(vec![(2, "b")]));
+        assert_eq!(grep_str("a\n", "^$"), Ok(vec![]));
+        assert_eq!(grep_str("", "x*"), Ok(vec![]));
+        assert!(grep_str("abc", "(a").is_err());
+    }
+
+    // Runs with and without the `pattern-cache` feature
+    #[test]
+    fn match_pattern_parses_on_every_call_or_from_the_cache() {
+        for _ in 0..2 {
+            assert!(match_pattern("log: ERROR 42", "ERROR \\d+"));
+            assert!(!match_pattern("log: ok", "ERROR \\d+"));
+            // An invalid pattern matches nothing
+            assert!(!match_pattern("(", "("));
         }
     }
-    false
-}
\ No newline at end of file
+
+    #[test]
+    fn nested_repeats_with_lookarounds_fail_quickly() {
+        for pattern in ["(a+)+b(?!c)", "(a*)*(?=b)", "(?<=a)(a*)*b", "(?<!b)(a+)+b"] {
+            let ast = parse(pattern);
+            for n in [1000, 2000] {
+                let input = a_run(n);
+                assert_eq!(search(&ast, &input, MatchOptions::default()), Ok(false), "{}", pattern);
+                assert_eq!(find(&ast, &input, MatchKind::LeftmostFirst, MatchOptions::default()), Ok(None));
+                assert_eq!(find_at(&ast, &input, 0, MatchKind::LeftmostFirst, MatchOptions::default()), Ok(None));
+            }
+        }
+    }
+
+    #[test]
+    fn nested_repeats_with_atomic_groups_are_memoized() {
+        for pattern in ["(?>a*)*b", "(a*+)*b", "(a+a+)+(?>b)"] {
+            let ast = parse(pattern);
+            let input = a_run(200);
+            assert_eq!(search(&ast, &input, MatchOptions::default()), Ok(false), "{}", pattern);
+            assert_eq!(find_at(&ast, &input, 0, MatchKind::LeftmostFirst, MatchOptions::default()), Ok(None));
+        }
+    }
+
+    #[test]
+    fn lookarounds_still_find_the_leftmost_match() {
+        let input: Vec<char> = "aaab aaac".chars().collect();
+        let ast = parse("(a+)+(?=c)");
+        assert_eq!(find(&ast, &input, MatchKind::LeftmostFirst, MatchOptions::default()), Ok(Some((5, 8))));
+        let (_, _, groups) = find_at(&ast, &input, 0, MatchKind::LeftmostFirst, MatchOptions::default()).unwrap().unwrap();
+        assert_eq!(groups.get(&1), Some(&(5, 8)));
+        let ast = parse("(?<=b )a+");
+        assert_eq!(find(&ast, &input, MatchKind::LeftmostFirst, MatchOptions::default()), Ok(Some((5, 8))));
+        let ast = parse("(?>a+)(?!b)");
+        assert_eq!(find(&ast, &input, MatchKind::LeftmostFirst, MatchOptions::default()), Ok(Some((5, 8))));
+    }
+
+    #[test]
+    fn ascii_mode_and_the_u_flag() {
+        let find_str = |pattern: &str, text: &str, ascii: bool| {
+            let input: Vec<char> = text.chars().collect();
+            let options = MatchOptions { ascii, ..MatchOptions::default() };
+            find(&parse(pattern), &input, MatchKind::LeftmostFirst, options)
+                .unwrap()
+                .map(|(start, end)| input[start..end].iter().collect::<String>())
+        };
+        assert_eq!(find_str("\\w+", "café", false), Some("café".to_string()));
+        assert_eq!(find_str("\\w+", "café", true), Some("caf".to_string()));
+        assert_eq!(find_str("(?-u)\\w+", "café", false), Some("caf".to_string()));
+        assert_eq!(find_str("(?u)\\w+", "café", true), Some("café".to_string()));
+        assert_eq!(find_str("(?-u:\\w+)(?u:\\w)", "café", false), Some("café".to_string()));
+        assert_eq!(find_str("(?-u).", "é", false), None);
+        assert_eq!(find_str("(?-u)\\s", "\u{a0}", false), None);
+        assert_eq!(find_str("\\s", "\u{a0}", false), Some("\u{a0}".to_string()));
+        assert_eq!(find_str("(?-u)[[:alpha:]]+", "éa", false), Some("a".to_string()));
+        // `\d` is ASCII either way
+        assert_eq!(find_str("\\d", "٣", false), None);
+    }
+
+    #[test]
+    fn horizontal_and_vertical_space() {
+        assert_eq!(first(r"\h+", "a \t\u{a0}\u{3000}b"), Some(" \t\u{a0}\u{3000}".to_string()));
+        assert_eq!(first(r"\h", "a\nb\rc\u{b}"), None);
+        assert_eq!(first(r"\v+", "a\n\r\u{b}\u{c}\u{85}\u{2028}\u{2029} b"), Some("\n\r\u{b}\u{c}\u{85}\u{2028}\u{2029}".to_string()));
+        assert_eq!(first(r"\v", "a \tb"), None);
+        assert_eq!(first(r"\H+", " \tab\ncd"), Some("ab\ncd".to_string()));
+        assert_eq!(first(r"\V+", "\nab \t\r"), Some("ab \t".to_string()));
+        assert_eq!(first(r"[\h\v]+", "x \n\ty"), Some(" \n\t".to_string()));
+        assert_eq!(first(r"[^\h]+", " ab "), Some("ab".to_string()));
+    }
+
+    #[test]
+    fn word_start_and_end() {
+        assert_eq!(first(r"\<cat\>", "concat cat catalog"), Some("cat".to_string()));
+        let input: Vec<char> = "concat cat catalog".chars().collect();
+        assert_eq!(find(&parse(r"\<cat\>"), &input, MatchKind::LeftmostFirst, MatchOptions::default()), Ok(Some((7, 10))));
+        assert_eq!(first(r"\<cat\>", "concat catalog"), None);
+        assert_eq!(first(r"\<cat", "concat catalog"), Some("cat".to_string()));
+        assert_eq!(first(r"cat\>", "catalog concat"), Some("cat".to_string()));
+        // `\<` needs a word char after it and `\>` one before it
+        assert_eq!(first(r"\<", "  "), None);
+        assert_eq!(first(r"\>", "  "), None);
+        assert_eq!(first(r"\<\w+\>", "--día--"), Some("día".to_string()));
+        assert_eq!(first(r"\<cat\>", "cat"), Some("cat".to_string()));
+    }
+
+    #[test]
+    fn end_of_input_before_or_after_a_final_newline() {
+        let matches = |pattern: &str, text: &str| first(pattern, text).is_some();
+        // `\Z` allows one final `\n` after it, `\z` doesn't
+        assert!(matches(r"a\Z", "a\n"));
+        assert!(matches(r"a\Z", "a"));
+        assert!(matches(r"a\z", "a"));
+        assert!(!matches(r"a\z", "a\n"));
+        // Only one newline may follow, and it must be the last char
+        assert!(!matches(r"a\Z", "a\n\n"));
+        assert!(!matches(r"a\Z", "a\nb"));
+        assert_eq!(first(r"a\Z\n", "a\n"), Some("a\n".to_string()));
+    }
+
+    #[test]
+    fn bounded_repeats() {
+        assert_eq!(first("a{2,4}", "aaaaa"), Some("aaaa".to_string()));
+        assert_eq!(first("x{3}", "xxxx"), Some("xxx".to_string()));
+        assert_eq!(first("a{2,}", "a aaaaa"), Some("aaaaa".to_string()));
+        assert_eq!(first(r"\d{4}", "in 2024!"), Some("2024".to_string()));
+        assert_eq!(first("a{3}", "aa"), None);
+        assert_eq!(first("x{0}y", "xy"), Some("y".to_string()));
+        // A brace that can't start a quantifier is a literal, as in grep
+        assert_eq!(first("a{b}", "a{b}"), Some("a{b}".to_string()));
+        assert_eq!(first("a{1", "a{1"), Some("a{1".to_string()));
+        assert_eq!(
+            Parser::new("a{2,1}").parse().err(),
+            Some("quantifier {2,1} at position 1 has its bounds out of order".to_string())
+        );
+    }
+
+    #[test]
+    fn class_ranges() {
+        assert_eq!(first("[a-z]+", "ABcdE"), Some("cd".to_string()));
+        assert_eq!(first("[0-9A-F]+", "x1aF3"), Some("1".to_string()));
+        assert_eq!(first("[0-9A-Fa-f]+", "x1aF3"), Some("1aF3".to_string()));
+        // A dash at either end, or escaped, is the dash itself
+        assert_eq!(first("[-a]+", "b-a-"), Some("-a-".to_string()));
+        assert_eq!(first("[a-]+", "b-a-"), Some("-a-".to_string()));
+        assert_eq!(first(r"[a\-z]+", "b-az"), Some("-az".to_string()));
+        assert_eq!(
+            Parser::new("[z-a]").parse().err(),
+            Some("range 'z-a' at position 1 is out of order".to_string())
+        );
+    }
+
+    #[test]
+    fn case_insensitive_literals_classes_and_backreferences() {
+        let options = MatchOptions { case_insensitive: true, ..MatchOptions::default() };
+        let first_i = |pattern: &str, text: &str| {
+            let input: Vec<char> = text.chars().collect();
+            find(&parse(pattern), &input, MatchKind::LeftmostFirst, options)
+                .unwrap()
+                .map(|(start, end)| input[start..end].iter().collect::<String>())
+        };
+        for text in ["Hello", "HELLO", "hElLo"] {
+            assert_eq!(first_i("hello", text), Some(text.to_string()));
+        }
+        assert_eq!(first_i("hello", "hell0"), None);
+        assert_eq!(first_i("[a-c]+", "xABc"), Some("ABc".to_string()));
+        assert_eq!(first_i(r"(a)\1", "aA"), Some("aA".to_string()));
+        assert_eq!(first(r"(a)\1", "aA"), None);
+        // Digits and word chars have no case to ignore
+        assert_eq!(first_i(r"\d\w", "x1A"), Some("1A".to_string()));
+        assert_eq!(first_i(r"\D", "1a"), Some("a".to_string()));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert_eq!(first(r"\bcat\b", "category cat"), Some("cat".to_string()));
+        assert_eq!(first(r"\bcat\b", "concatenate"), None);
+        assert_eq!(first(r"\Bat", "cat at"), Some("at".to_string()));
+        // The edges of the input count as non-word chars
+        assert_eq!(first(r"\bx", "x"), Some("x".to_string()));
+        assert_eq!(first(r"x\b", "x"), Some("x".to_string()));
+        assert_eq!(first(r"\b", " "), None);
+        // Empty input has no word char, so no boundary, and `\B` matches there
+        assert_eq!(first(r"\b", ""), None);
+        assert_eq!(first(r"\B", ""), Some(String::new()));
+    }
+
+    #[test]
+    fn lazy_repeats_prefer_the_shortest_match() {
+        assert_eq!(first("a.*?b", "axbxb"), Some("axb".to_string()));
+        assert_eq!(first("a.*b", "axbxb"), Some("axbxb".to_string()));
+        assert_eq!(first("<.+?>", "<a><b>"), Some("<a>".to_string()));
+        assert_eq!(first("a+?", "aaa"), Some("a".to_string()));
+        assert_eq!(first("a{2,3}?", "aaaa"), Some("aa".to_string()));
+        // `??` is a lazy `?`, which still matches when it has to
+        assert_eq!(first("a??", "a"), Some(String::new()));
+        assert_eq!(first("a??b", "ab"), Some("ab".to_string()));
+        assert_eq!(
+            Parser::new("a???").parse().err(),
+            Some("quantifier '?' at position 3 has nothing to repeat".to_string())
+        );
+    }
+
+    #[test]
+    fn named_backreferences_match_their_groups_text() {
+        assert_eq!(first(r#"(?<q>['"]).*?\k<q>"#, r#"say 'hi" there' ok"#), Some(r#"'hi" there'"#.to_string()));
+        assert_eq!(first(r"(?P<w>\w)(?P=w)", "abccd"), Some("cc".to_string()));
+        assert_eq!(first(r"(?<a>x)(?<b>y)\k<b>\k<a>", "xyxyyx"), Some("xyyx".to_string()));
+    }
+
+    #[test]
+    fn absolute_anchors_ignore_multi_line() {
+        let options = MatchOptions { multi_line: true, ..MatchOptions::default() };
+        let matches_lines = |pattern: &str, text: &str| {
+            let input: Vec<char> = text.chars().collect();
+            search(&parse(pattern), &input, options) == Ok(true)
+        };
+        assert!(matches_lines("^b", "a\nb"));
+        assert!(!matches_lines(r"\Ab", "a\nb"));
+        assert!(matches_lines("a$", "a\nb"));
+        assert!(!matches_lines(r"a\z", "a\nb"));
+        assert!(!matches_lines(r"a\Z", "a\nb"));
+        assert_eq!(first(r"\Aab", "xab"), None);
+        assert_eq!(first(r"\Aab", "abx"), Some("ab".to_string()));
+    }
+
+    #[test]
+    fn hex_escapes_match_control_chars() {
+        assert_eq!(first(r"\x1b\[\d+m", "\x1b[31mred"), Some("\x1b[31m".to_string()));
+        assert_eq!(first(r"[\x{1F600}-\x{1F64F}]+", "hi 😀🙏!"), Some("😀🙏".to_string()));
+    }
+
+    #[test]
+    fn control_escapes_match_their_chars() {
+        assert_eq!(first(r"a\tb", "a\tb"), Some("a\tb".to_string()));
+        assert_eq!(first(r"a\tb", "atb"), None);
+        assert_eq!(first(r"[\n\r]+", "a\r\nb"), Some("\r\n".to_string()));
+        assert_eq!(first(r"\v", "a\x0bb"), Some("\x0b".to_string()));
+        assert_eq!(first(r"\0", "a\0b"), Some("\0".to_string()));
+    }
+
+    #[test]
+    fn inline_flags() {
+        assert_eq!(first("(?i)hello", "say HeLLo"), Some("HeLLo".to_string()));
+        assert_eq!(first("(?s)a.b", "a\nb"), Some("a\nb".to_string()));
+        assert_eq!(first("a.b", "a\nb"), None);
+        assert_eq!(first("(?m)^x$", "a\nx\nb"), Some("x".to_string()));
+        assert_eq!(first("(?im)^B", "a\nb"), Some("b".to_string()));
+    }
+
+    #[test]
+    fn scoped_flags_apply_only_inside_their_group() {
+        assert_eq!(first("(?i:error)|WARN", "an ERROR"), Some("ERROR".to_string()));
+        assert_eq!(first("(?i:error)|WARN", "warn"), None);
+        assert_eq!(first("a(?i:b)c", "aBc"), Some("aBc".to_string()));
+        assert_eq!(first("a(?i:b)c", "aBC"), None);
+        // An inner group can turn a flag back off
+        assert_eq!(first("(?i:a(?-i:b))", "Ab"), Some("Ab".to_string()));
+        assert_eq!(first("(?i:a(?-i:b))", "AB"), None);
+    }
+
+    #[test]
+    fn free_spacing_keeps_escaped_and_class_whitespace() {
+        assert_eq!(first("(?x) a b # comment\n c", "abc"), Some("abc".to_string()));
+        assert_eq!(first("(?x) a b", "a b"), None);
+        assert_eq!(first(r"(?x)a\ b", "a b"), Some("a b".to_string()));
+        assert_eq!(first("(?x)[ a]", "x y"), Some(" ".to_string()));
+    }
+
+    #[test]
+    fn quoted_text_matches_literally() {
+        assert_eq!(first(r"\Qa.b*(c)\E", "xa.b*(c)"), Some("a.b*(c)".to_string()));
+        assert_eq!(first(r"\Qa.b*(c)\E", "aab(c)"), None);
+        assert_eq!(first(r"\Qa.b*\E+", "a.b**"), Some("a.b**".to_string()));
+        assert_eq!(first(r"\Qa\", r"a\"), Some(r"a\".to_string()));
+    }
+
+    #[test]
+    fn unicode_categories() {
+        assert_eq!(first(r"\p{L}+", "12été3"), Some("été".to_string()));
+        assert_eq!(first(r"\pN+", "ab٣4"), Some("٣4".to_string()));
+        assert_eq!(first(r"\P{L}+", "ab12c"), Some("12".to_string()));
+        assert_eq!(first(r"\p{Lu}", "abC"), Some("C".to_string()));
+        // Unlike `\w`, a letter class leaves out digits and `_`
+        assert_eq!(first(r"\p{L}+", "_9x"), Some("x".to_string()));
+    }
+
+    #[test]
+    fn unicode_scripts_and_blocks() {
+        assert_eq!(first(r"\p{Greek}+", "abc αβγ"), Some("αβγ".to_string()));
+        assert_eq!(first(r"\p{Script=Cyrillic}+", "abc Привет"), Some("Привет".to_string()));
+        assert_eq!(first(r"\p{sc=Greek}", "α"), Some("α".to_string()));
+        assert_eq!(first(r"\p{Block=Basic Latin}+", "é abc"), Some(" abc".to_string()));
+        assert_eq!(first(r"\P{InBasicLatin}+", "abc αβ"), Some("αβ".to_string()));
+    }
+
+    #[test]
+    fn case_insensitive_uses_simple_case_folding() {
+        assert_eq!(first("(?i)σ", "ς"), Some("ς".to_string()));
+        assert_eq!(first("(?i)ς", "Σ"), Some("Σ".to_string()));
+        assert_eq!(first("(?i)k", "\u{212A}"), Some("\u{212A}".to_string()));
+        assert_eq!(first("(?i)ǅ", "ǆ"), Some("ǆ".to_string()));
+        assert_eq!(first("(?i)été", "ÉTÉ"), Some("ÉTÉ".to_string()));
+        // One char folds to one char: `ß` matches `ẞ` but not `SS`
+        assert_eq!(first("(?i)ß", "ẞ"), Some("ẞ".to_string()));
+        assert_eq!(first("(?i)straße", "STRASSE"), None);
+    }
+
+    #[test]
+    fn multi_line_anchors_match_at_line_boundaries() {
+        let find_lines = |pattern: &str, text: &str, multi_line: bool| {
+            let input: Vec<char> = text.chars().collect();
+            let options = MatchOptions { multi_line, ..MatchOptions::default() };
+            find(&parse(pattern), &input, MatchKind::LeftmostFirst, options).unwrap()
+        };
+        assert_eq!(find_lines("^b$", "a\nb\nc", true), Some((2, 3)));
+        assert_eq!(find_lines("^b$", "a\nb\nc", false), None);
+        assert_eq!(find_lines("a$", "a\nb", true), Some((0, 1)));
+        assert_eq!(find_lines("^", "a\n", true), Some((0, 0)));
+        assert_eq!(find_lines("^c", "a\r\nc", true), Some((3, 4)));
+        assert_eq!(format!("{:?}", parse("(?m)^")), "Scoped { node: Seq([StartAnchor]), flags: Flags { case_insensitive: None, multi_line: Some(true), dot_all: None, unicode: None, extended: None } }");
+    }
+
+    #[test]
+    fn dot_all_lets_dot_match_newlines() {
+        let matches_dot = |pattern: &str, text: &str, dot_all: bool| {
+            let input: Vec<char> = text.chars().collect();
+            let options = MatchOptions { dot_all, ..MatchOptions::default() };
+            search(&parse(pattern), &input, options) == Ok(true)
+        };
+        assert!(!matches_dot("a.b", "a\nb", false));
+        assert!(matches_dot("a.b", "a\nb", true));
+        assert!(matches_dot("a.b", "a\rb", false));
+        assert!(matches_dot("(?s)a.b", "a\nb", false));
+        assert!(!matches_dot("(?-s)a.b", "a\nb", true));
+        assert!(matches_dot("a[^x]b", "a\nb", false));
+    }
+
+    #[test]
+    fn grapheme_clusters() {
+        assert_eq!(first(r"\X", "e\u{301}x"), Some("e\u{301}".to_string()));
+        assert_eq!(first(r"^\X{2}$", "👍🏽a"), Some("👍🏽a".to_string()));
+        assert_eq!(first(r"^\X$", "🇫🇷"), Some("🇫🇷".to_string()));
+        assert_eq!(first(r"^\X$", "\r\n"), Some("\r\n".to_string()));
+        assert_eq!(first(r"^\X$", "ab"), None);
+        assert_eq!(first(r"\X", ""), None);
+        assert_eq!(format!("{:?}", parse(r"\X")), "Seq([Grapheme])");
+    }
+}
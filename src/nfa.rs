@@ -0,0 +1,404 @@
+use std::mem;
+
+use crate::matcher::{assertion_holds, char_matches, consumes_char, width, MatchError, MatchOptions};
+use crate::parser::{Flags, RegexNode, RepeatKind};
+
+// Programs longer than this aren't built (think `(a{1000}){1000}`); the
+// backtracking matcher handles those patterns instead
+const MAX_INSTS: usize = 10_000;
+
+// One instruction of a Thompson NFA
+#[derive(Debug, Clone)]
+enum Inst {
+    // Consume one char matching this single-char node, under these inline flags
+    Char(RegexNode, Flags),
+    // Continue only if this anchor or word boundary holds here
+    Assert(RegexNode, Flags),
+    // Continue only if this lookaround (an index into `Program::looks`) holds here
+    Look(usize),
+    // Continue at both targets
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+// A pattern compiled to a Thompson NFA, for answering "does this match
+// anywhere?" by running every possible path side by side. That takes time
+// linear in the input (times the program length) however the repeats nest,
+// where backtracking can take polynomial or worse time on patterns like
+// `(a*)*b`. Patterns with backreferences or atomic groups can't be compiled and
+// stay on the backtracking matcher.
+#[derive(Debug, Clone)]
+pub struct Program {
+    insts: Vec<Inst>,
+    looks: Vec<Look>,
+}
+
+// A lookahead or lookbehind, whose body is run as a program of its own at each
+// position it's asked about
+#[derive(Debug, Clone)]
+struct Look {
+    program: Program,
+    behind: bool,
+    negated: bool,
+    // The fewest and most chars the body can span, which bound where a
+    // lookbehind's match can start
+    width: (usize, Option<usize>),
+}
+
+impl Look {
+    // Does the lookaround hold at `pos`?
+    fn holds(&self, input: &[char], pos: usize, options: MatchOptions, steps: &mut usize) -> Result<bool, MatchError> {
+        let found = if !self.behind {
+            self.program.matches_within(input, pos, pos, None, options, steps)?
+        } else if self.width.0 > pos {
+            false
+        } else {
+            let (min, max) = self.width;
+            let earliest = max.map_or(0, |max| pos.saturating_sub(max));
+            self.program.matches_within(input, earliest, pos - min, Some(pos), options, steps)?
+        };
+        Ok(found != self.negated)
+    }
+}
+
+// The threads alive at one position: their instructions and where their match
+// started, in order of start. Each instruction is followed only once per
+// position, by the thread that started first.
+struct Threads {
+    pcs: Vec<(usize, usize)>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Threads { pcs: Vec::new(), seen: vec![false; len] }
+    }
+
+    fn clear(&mut self) {
+        self.pcs.clear();
+        self.seen.iter_mut().for_each(|s| *s = false);
+    }
+}
+
+impl Program {
+    // Compile the AST, or return None if it has a backreference or atomic group,
+    // or is too big
+    pub fn compile(ast: &RegexNode) -> Option<Program> {
+        Program::compile_within(ast, Flags::default())
+    }
+
+    // Compile the AST as if inside groups that set `flags`
+    fn compile_within(ast: &RegexNode, flags: Flags) -> Option<Program> {
+        let mut program = Program { insts: Vec::new(), looks: Vec::new() };
+        program.emit(ast, flags)?;
+        program.push(Inst::Match)?;
+        Some(program)
+    }
+
+    fn push(&mut self, inst: Inst) -> Option<usize> {
+        if self.insts.len() >= MAX_INSTS {
+            return None;
+        }
+        self.insts.push(inst);
+        Some(self.insts.len() - 1)
+    }
+
+    // Point the placeholder at `at` to `target` (a Split's second branch)
+    fn patch(&mut self, at: usize, target: usize) {
+        self.insts[at] = match self.insts[at] {
+            Inst::Split(first, _) => Inst::Split(first, target),
+            _ => Inst::Jump(target),
+        };
+    }
+
+    // Emit the instructions for `node`, where `flags` are the inline flags of the
+    // `(?i)`-style groups around it
+    fn emit(&mut self, node: &RegexNode, flags: Flags) -> Option<()> {
+        match node {
+            RegexNode::Seq(nodes) => {
+                for n in nodes {
+                    self.emit(n, flags)?;
+                }
+            }
+            // An empty alternation (from zero patterns) matches nothing
+            RegexNode::Alt(branches) if branches.is_empty() => {
+                self.push(Inst::Char(RegexNode::CharClass { items: Vec::new(), negated: false }, flags))?;
+            }
+            RegexNode::Alt(branches) => {
+                // split L1, L2 / L1: branch; jump end / L2: split ... / last branch / end:
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 == branches.len() {
+                        self.emit(branch, flags)?;
+                        break;
+                    }
+                    let split = self.push(Inst::Split(self.insts.len() + 1, 0))?;
+                    self.emit(branch, flags)?;
+                    jumps.push(self.push(Inst::Jump(0))?);
+                    self.patch(split, self.insts.len());
+                }
+                for jump in jumps {
+                    self.patch(jump, self.insts.len());
+                }
+            }
+            // Captures don't matter for whether there is a match
+            RegexNode::Group { node: inner, .. } => self.emit(inner, flags)?,
+            RegexNode::Scoped { node: inner, flags: inner_flags } => self.emit(inner, inner_flags.within(flags))?,
+            RegexNode::Repeat { node: inner, kind, .. } => {
+                let (min, max) = match *kind {
+                    RepeatKind::ZeroOrOne => (0, Some(1)),
+                    RepeatKind::OneOrMore => (1, None),
+                    RepeatKind::ZeroOrMore => (0, None),
+                    RepeatKind::Bounded { min, max } => (min, max),
+                };
+                // Also stops an empty body like `(){1000000}` from looping for ages
+                if min > MAX_INSTS || max.is_some_and(|max| max > MAX_INSTS) {
+                    return None;
+                }
+                for _ in 0..min {
+                    self.emit(inner, flags)?;
+                }
+                match max {
+                    // loop: split body, end / body / jump loop / end:
+                    None => {
+                        let split = self.push(Inst::Split(self.insts.len() + 1, 0))?;
+                        self.emit(inner, flags)?;
+                        self.push(Inst::Jump(split))?;
+                        self.patch(split, self.insts.len());
+                    }
+                    // Each optional copy can be skipped, which skips the rest too
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in min..max {
+                            splits.push(self.push(Inst::Split(self.insts.len() + 1, 0))?);
+                            self.emit(inner, flags)?;
+                        }
+                        for split in splits {
+                            self.patch(split, self.insts.len());
+                        }
+                    }
+                }
+            }
+            RegexNode::Lookahead { node: inner, negated } | RegexNode::Lookbehind { node: inner, negated } => {
+                let look = Look {
+                    program: Program::compile_within(inner, flags)?,
+                    behind: matches!(node, RegexNode::Lookbehind { .. }),
+                    negated: *negated,
+                    width: width(inner),
+                };
+                // The body's instructions count towards the limit too
+                if self.insts.len() + look.program.insts.len() >= MAX_INSTS {
+                    return None;
+                }
+                self.looks.push(look);
+                self.push(Inst::Look(self.looks.len() - 1))?;
+            }
+            // Backreferences need the captured text, atomic groups a preference
+            // between paths, and `\X` can span any number of chars
+            RegexNode::Backreference(_) | RegexNode::Grapheme | RegexNode::Atomic(_) => return None,
+            RegexNode::StartAnchor
+            | RegexNode::EndAnchor
+            | RegexNode::InputStart
+            | RegexNode::InputEnd { .. }
+            | RegexNode::WordStart
+            | RegexNode::WordEnd
+            | RegexNode::WordBoundary { .. } => {
+                self.push(Inst::Assert(node.clone(), flags))?;
+            }
+            _ if consumes_char(node) => {
+                self.push(Inst::Char(node.clone(), flags))?;
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    // Does the program match anywhere in `input`? Every thread followed counts
+    // as one step towards `options.step_limit`.
+    pub fn is_match(&self, input: &[char], options: MatchOptions) -> Result<bool, MatchError> {
+        Ok(self.run(input, 0, options, true)?.is_some())
+    }
+
+    // Where the leftmost match at or after `from` starts, if there is one. The
+    // backtracking matcher then only needs to try that one start.
+    pub fn leftmost_start(&self, input: &[char], from: usize, options: MatchOptions) -> Result<Option<usize>, MatchError> {
+        self.run(input, from, options, false)
+    }
+
+    // Run every thread side by side from `from`, returning the start of the
+    // leftmost match. With `first`, stop at the first match found instead.
+    fn run(&self, input: &[char], from: usize, options: MatchOptions, first: bool) -> Result<Option<usize>, MatchError> {
+        let mut current = Threads::new(self.insts.len());
+        let mut next = Threads::new(self.insts.len());
+        let mut steps = 0;
+        let steps = &mut steps;
+        let mut found: Option<usize> = None;
+        for pos in from..=input.len() {
+            // Starting a thread at every position makes this a search. Once a
+            // match is found, later starts can't be leftmost.
+            if found.is_none() {
+                found = self.add(&mut current, (0, pos), input, pos, options, steps)?;
+            }
+            if let Some(found) = found {
+                // Done unless a thread that started earlier is still running
+                if first || !matches!(current.pcs.first(), Some(&(_, start)) if start < found) {
+                    break;
+                }
+            }
+            for i in 0..current.pcs.len() {
+                let (pc, start) = current.pcs[i];
+                // Only threads that started earlier can still beat the match found
+                if found.is_some_and(|found| start >= found) {
+                    break;
+                }
+                if let Inst::Char(node, flags) = &self.insts[pc] {
+                    if pos < input.len() && char_matches(node, input[pos], options.with_flags(*flags)) {
+                        if let Some(start) = self.add(&mut next, (pc + 1, start), input, pos + 1, options, steps)? {
+                            found = Some(start);
+                            if first {
+                                return Ok(found);
+                            }
+                        }
+                    }
+                }
+            }
+            mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        Ok(found)
+    }
+
+    // Is there a match starting between `earliest` and `latest`, and ending at
+    // `end` (or anywhere, if that's None)? Lookarounds ask this of their body.
+    fn matches_within(
+        &self,
+        input: &[char],
+        earliest: usize,
+        latest: usize,
+        end: Option<usize>,
+        options: MatchOptions,
+        steps: &mut usize,
+    ) -> Result<bool, MatchError> {
+        let mut current = Threads::new(self.insts.len());
+        let mut next = Threads::new(self.insts.len());
+        let last = end.unwrap_or(input.len());
+        for pos in earliest..=last {
+            if pos <= latest && self.add(&mut current, (0, pos), input, pos, options, steps)?.is_some() && end.map_or(true, |end| end == pos) {
+                return Ok(true);
+            }
+            if current.pcs.is_empty() && pos >= latest {
+                break;
+            }
+            for i in 0..current.pcs.len() {
+                let (pc, start) = current.pcs[i];
+                if let Inst::Char(node, flags) = &self.insts[pc] {
+                    if pos < last
+                        && char_matches(node, input[pos], options.with_flags(*flags))
+                        && self.add(&mut next, (pc + 1, start), input, pos + 1, options, steps)?.is_some()
+                        && end.map_or(true, |end| end == pos + 1)
+                    {
+                        return Ok(true);
+                    }
+                }
+            }
+            mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        Ok(false)
+    }
+
+    // Follow jumps, splits and assertions from the thread's instruction at
+    // `pos`, adding each `Char` reached to `threads`. Returns the thread's start
+    // if `Match` was reached.
+    fn add(
+        &self,
+        threads: &mut Threads,
+        (pc, start): (usize, usize),
+        input: &[char],
+        pos: usize,
+        options: MatchOptions,
+        steps: &mut usize,
+    ) -> Result<Option<usize>, MatchError> {
+        let mut stack = vec![pc];
+        let mut matched = None;
+        while let Some(pc) = stack.pop() {
+            if threads.seen[pc] {
+                continue;
+            }
+            threads.seen[pc] = true;
+            *steps += 1;
+            if options.step_limit.is_some_and(|limit| *steps > limit) {
+                return Err(MatchError::ComplexityLimit);
+            }
+            match &self.insts[pc] {
+                Inst::Match => matched = Some(start),
+                Inst::Jump(target) => stack.push(*target),
+                // Push the second branch first so the first is followed first
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                }
+                Inst::Assert(node, flags) => {
+                    if assertion_holds(node, input, pos, options.with_flags(*flags)) {
+                        stack.push(pc + 1);
+                    }
+                }
+                Inst::Look(look) => {
+                    if self.looks[*look].holds(input, pos, options, steps)? {
+                        stack.push(pc + 1);
+                    }
+                }
+                Inst::Char(..) => threads.pcs.push((pc, start)),
+            }
+        }
+        Ok(matched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::search_with;
+    use crate::parser::Parser;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    // The NFA and the backtracking matcher agree on whether there's a match
+    fn agree(pattern: &str, inputs: &[&str]) {
+        let ast = Parser::new(pattern).parse().unwrap();
+        let program = Program::compile(&ast).expect("compiles");
+        for input in inputs {
+            let input = chars(input);
+            let options = MatchOptions::default();
+            assert_eq!(
+                program.is_match(&input, options),
+                search_with(&ast, None, &input, options),
+                "{} on {:?}",
+                pattern,
+                input.iter().collect::<String>()
+            );
+        }
+    }
+
+    #[test]
+    fn lookarounds_agree_with_backtracking() {
+        let inputs = ["", "a", "ab", "ac", "ba", "abc", "aab", "xaby", "bab", "cab", "a\nb", "aaa"];
+        for pattern in [
+            "a(?=b)", "a(?!b)", "(?<=a)b", "(?<!a)b", "(?<=^a)b", "a(?=b$)", "(?<=a|ba)b", "(?<=a+)b",
+            "(?<!x*a)b", "(?=a)(?!ab)", "(a(?=b))+", "(?<=(?<=a)b)", "(?i)(?<=A)B", "a(?=.*c)", "\\b(?<=a)",
+        ] {
+            agree(pattern, &inputs);
+        }
+    }
+
+    #[test]
+    fn atomic_groups_and_backreferences_do_not_compile() {
+        for pattern in ["(?>a*)b", "a*+b", "(a)\\1", "(?=(?>a))", "\\X"] {
+            let ast = Parser::new(pattern).parse().unwrap();
+            assert!(Program::compile(&ast).is_none(), "{}", pattern);
+        }
+    }
+}
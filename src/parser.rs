@@ -1,53 +1,234 @@
+use crate::unicode_tables::{BLOCKS, GENERAL_CATEGORIES, SCRIPTS};
+
 // AST for regex
 #[derive(Debug, Clone)]
-// Minimal AST for the features we support: concat, alternation, ?, +, anchors, ., \d, \w, classes, literals
+// Minimal AST for the features we support: concat, alternation, ?, +, anchors, ., \d, \w, \s, classes, literals
 pub enum RegexNode {
     Seq(Vec<RegexNode>),
     Alt(Vec<RegexNode>),
     Repeat {
         node: Box<RegexNode>,
         kind: RepeatKind,
+        // false for a lazy quantifier like `*?`, which prefers fewer repetitions
+        greedy: bool,
     },
     StartAnchor,
     EndAnchor,
+    // \A: the start of the input, even once `^` can match after line breaks
+    InputStart,
+    // \z: the very end of the input; \Z (`before_newline`) also allows one
+    // final `\n` after it
+    InputEnd { before_newline: bool },
+    // \< / \>: zero-width start and end of a word
+    WordStart,
+    WordEnd,
+    // \b / \B: zero-width word boundary, or anywhere that isn't one
+    WordBoundary { negated: bool },
     Dot,
-    Digit,
-    Word,
+    // \d / \D: an ASCII digit, or anything else
+    Digit { negated: bool },
+    // \w / \W: a word char (letter, digit or `_`), or anything else
+    Word { negated: bool },
+    // \s / \S: whitespace (`char::is_whitespace`, or ASCII only in ascii mode), or anything else
+    Space { negated: bool },
+    // \h / \H: horizontal whitespace (space, tab, ...) or anything else
+    HorizontalSpace { negated: bool },
+    // \v / \V: vertical whitespace (line breaks, form feed, ...) or anything else
+    VerticalSpace { negated: bool },
     CharClass {
-        chars: Vec<char>,
+        items: Vec<ClassItem>,
         negated: bool,
     },
     Literal(char),
+    // \X: one extended grapheme cluster, such as `e` plus a combining accent or
+    // an emoji with its modifiers, however many chars it spans
+    Grapheme,
     Backreference(usize),
     Group {
         group_num: usize,
         node: Box<RegexNode>,
     },
+    // (?=...) / (?!...): zero-width, holds if `node` matches (or doesn't) here
+    Lookahead {
+        node: Box<RegexNode>,
+        negated: bool,
+    },
+    // (?<=...) / (?<!...): zero-width, holds if `node` matches (or doesn't) text
+    // ending here
+    Lookbehind {
+        node: Box<RegexNode>,
+        negated: bool,
+    },
+    // (?>...), and possessive quantifiers like `a++`: only the first way `node`
+    // matches is kept, so nothing after it can backtrack into it
+    Atomic(Box<RegexNode>),
+    // `node` matched with inline flags like `(?i)` turned on or off
+    Scoped {
+        node: Box<RegexNode>,
+        flags: Flags,
+    },
+}
+
+impl RegexNode {
+    // Renumber every group and backreference by `offset`, for a pattern whose
+    // groups come after another pattern's
+    pub(crate) fn shift_groups(&mut self, offset: usize) {
+        match self {
+            RegexNode::Seq(nodes) | RegexNode::Alt(nodes) => {
+                nodes.iter_mut().for_each(|n| n.shift_groups(offset))
+            }
+            RegexNode::Repeat { node, .. }
+            | RegexNode::Lookahead { node, .. }
+            | RegexNode::Lookbehind { node, .. }
+            | RegexNode::Atomic(node)
+            | RegexNode::Scoped { node, .. } => node.shift_groups(offset),
+            RegexNode::Group { group_num, node } => {
+                *group_num += offset;
+                node.shift_groups(offset);
+            }
+            RegexNode::Backreference(n) => *n += offset,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+// One member of a character class: a single char, an inclusive range like `a-z`,
+// a set escape like `\d` or `\W`, a POSIX class like `[:alpha:]` or `[:^digit:]`,
+// a Unicode property like `\p{L}` (as its sorted table of char ranges), a nested
+// class like `[0-9]` in `[[a-z][0-9]]`, or a set operation between the
+// members on either side of a `&&` or `--`
+pub enum ClassItem {
+    Single(char),
+    Range(char, char),
+    Shorthand { set: Shorthand, negated: bool },
+    Posix { class: PosixClass, negated: bool },
+    Unicode { ranges: &'static [(char, char)], negated: bool },
+    Class { items: Vec<ClassItem>, negated: bool },
+    SetOp { op: SetOp, left: Vec<ClassItem>, right: Vec<ClassItem> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// `&&`: chars in both operands; `--`: chars in the left operand but not the right
+pub enum SetOp {
+    Intersection,
+    Difference,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// The sets named by `\d`, `\w`, `\s`, `\h` and `\v` (negated by the uppercase letter)
+pub enum Shorthand {
+    Digit,
+    Word,
+    Space,
+    HorizontalSpace,
+    VerticalSpace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// The POSIX classes usable inside brackets, as in `[[:alpha:]_]`
+pub enum PosixClass {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Word,
+    Xdigit,
+}
+
+impl PosixClass {
+    fn from_name(name: &str) -> Option<PosixClass> {
+        Some(match name {
+            "alnum" => PosixClass::Alnum,
+            "alpha" => PosixClass::Alpha,
+            "blank" => PosixClass::Blank,
+            "cntrl" => PosixClass::Cntrl,
+            "digit" => PosixClass::Digit,
+            "graph" => PosixClass::Graph,
+            "lower" => PosixClass::Lower,
+            "print" => PosixClass::Print,
+            "punct" => PosixClass::Punct,
+            "space" => PosixClass::Space,
+            "upper" => PosixClass::Upper,
+            "word" => PosixClass::Word,
+            "xdigit" => PosixClass::Xdigit,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+// Inline flags from a group like `(?i)` or `(?s-i)`. Each is Some(true) when the
+// group turns it on, Some(false) when it turns it off, and None when it leaves
+// it as it was.
+pub struct Flags {
+    // `i`: ignore case
+    pub case_insensitive: Option<bool>,
+    // `m`: `^` and `$` also match at line breaks
+    pub multi_line: Option<bool>,
+    // `s`: `.` also matches `\n`
+    pub dot_all: Option<bool>,
+    // `u`: `\w`, `\s`, `.` and the POSIX classes are Unicode-aware; `(?-u)` is
+    // the inline form of `--ascii`
+    pub unicode: Option<bool>,
+    // `x`: whitespace and `#` comments in the pattern are ignored. Only the
+    // parser uses this.
+    pub extended: Option<bool>,
+}
+
+impl Flags {
+    // These flags inside a group with `outer` flags: wherever both say
+    // something, these win
+    pub(crate) fn within(self, outer: Flags) -> Flags {
+        Flags {
+            case_insensitive: self.case_insensitive.or(outer.case_insensitive),
+            multi_line: self.multi_line.or(outer.multi_line),
+            dot_all: self.dot_all.or(outer.dot_all),
+            unicode: self.unicode.or(outer.unicode),
+            extended: self.extended.or(outer.extended),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
-// The only quantifiers we currently support
+// The quantifiers we support
 pub enum RepeatKind {
     ZeroOrOne,
     OneOrMore,
     ZeroOrMore,
+    // {n}, {n,} and {n,m}: between `min` and `max` (or unlimited) repetitions
+    Bounded { min: usize, max: Option<usize> },
 }
 
 // A tiny recursive-descent parser (EBNF):
+//   pattern := ('(?' flags ')')* alt
 //   alt := seq ('|' seq)*
 //   seq := repeat*
-//   repeat := atom ('?' | '+' | '*')?
-//   atom := '(' alt ')' | '[' '^'? class ']' | '\\' esc | '.' | '^' | '$' | literal
+//   repeat := atom (('?' | '+' | '*' | '{' n (',' m?)? '}') ('?' | '+')?)?
+//   atom := '(' ('?:' | '?' flags ':' | '?<' name '>' | '?P<' name '>' | '?=' | '?!' | '?<=' | '?<!' | '?>')? alt ')'
+//         | '(?P=' name ')'
+//         | '[' '^'? class ']' | '\\' esc | '.' | '^' | '$' | literal
 pub struct Parser<'a> {
     pattern: &'a str,
     pos: usize,
     ref_count: usize,
+    // Named groups seen so far, with their group numbers
+    names: Vec<(String, usize)>,
+    // In free-spacing mode (`(?x)`), whitespace and `#` comments are skipped
+    extended: bool,
 }
 
 impl<'a> Parser<'a> {
     // Create a new parser for the given pattern
     pub fn new(pattern: &'a str) -> Self {
-        Self { pattern, pos: 0, ref_count: 0 }
+        Self { pattern, pos: 0, ref_count: 0, names: Vec::new(), extended: false }
     }
 
     // Peek at the current character without advancing
@@ -72,105 +253,432 @@ impl<'a> Parser<'a> {
         }
     }
 
-    // Parse the pattern, starting from the top-level alternation
-    pub fn parse(&mut self) -> RegexNode {
-        self.parse_alt()
+    // Parse the pattern, starting from the top-level alternation. Inline flags
+    // like `(?i)` at the very start apply to the whole pattern.
+    pub fn parse(&mut self) -> Result<RegexNode, String> {
+        let mut flags: Option<Flags> = None;
+        while let Some(more) = self.parse_flags(')')? {
+            flags = Some(more.within(flags.unwrap_or_default()));
+        }
+        self.extended = flags.and_then(|f| f.extended).unwrap_or(false);
+        let mut node = self.parse_alt()?;
+        // parse_seq only stops early at a ')' that no group is waiting for
+        if self.peek() == Some(')') {
+            return Err(format!("unmatched ')' at position {}", self.pos));
+        }
+        if let Some(flags) = flags {
+            node = RegexNode::Scoped {
+                node: Box::new(node),
+                flags,
+            };
+        }
+        Ok(node)
+    }
+
+    // Parse the start of a flag group like `(?i)` or `(?m-s:` at the current
+    // position, if there is one ending in `end`: flags before a `-` are turned
+    // on, flags after it off
+    fn parse_flags(&mut self, end: char) -> Result<Option<Flags>, String> {
+        let start = self.pos;
+        let rest = &self.pattern[self.pos..];
+        let body = match rest.strip_prefix("(?").and_then(|r| r.find([')', ':']).map(|i| (&r[..i], &r[i..]))) {
+            Some((body, after))
+                if !body.is_empty() && after.starts_with(end) && body.chars().all(|c| c.is_ascii_alphabetic() || c == '-') =>
+            {
+                body
+            }
+            _ => return Ok(None),
+        };
+        let mut flags = Flags::default();
+        let mut on = true;
+        for c in body.chars() {
+            let flag = match c {
+                '-' if on => {
+                    on = false;
+                    continue;
+                }
+                'i' => &mut flags.case_insensitive,
+                'm' => &mut flags.multi_line,
+                's' => &mut flags.dot_all,
+                'u' => &mut flags.unicode,
+                'x' => &mut flags.extended,
+                _ => return Err(format!("unsupported inline flag '{}' at position {}", c, start)),
+            };
+            *flag = Some(on);
+        }
+        self.pos += body.len() + 3;
+        Ok(Some(flags))
+    }
+
+    // Number of capturing groups seen so far (all of them, once `parse` returns)
+    pub fn group_count(&self) -> usize {
+        self.ref_count
+    }
+
+    // The named groups seen so far and their numbers, in order of appearance
+    pub fn group_names(&self) -> &[(String, usize)] {
+        &self.names
     }
 
     // Parse alternation: alt := seq ('|' seq)*
-    fn parse_alt(&mut self) -> RegexNode {
+    fn parse_alt(&mut self) -> Result<RegexNode, String> {
         let mut branches = Vec::new();
-        branches.push(self.parse_seq());
+        branches.push(self.parse_seq()?);
         while self.peek() == Some('|') {
             self.advance();
-            branches.push(self.parse_seq());
+            branches.push(self.parse_seq()?);
         }
         if branches.len() == 1 {
-            branches.pop().unwrap()
+            Ok(branches.pop().unwrap())
         } else {
-            RegexNode::Alt(branches)
+            Ok(RegexNode::Alt(branches))
         }
     }
 
     // Parse sequence: seq := repeat*
-    fn parse_seq(&mut self) -> RegexNode {
+    fn parse_seq(&mut self) -> Result<RegexNode, String> {
         let mut nodes = Vec::new();
-        while let Some(ch) = self.peek() {
+        loop {
+            self.skip_ignored()?;
+            let Some(ch) = self.peek() else {
+                break;
+            };
             // Stop at sequence terminators
             if ch == ')' || ch == '|' {
                 break;
             }
-            nodes.push(self.parse_repeat());
+            // `\Q...\E` quotes its text, so each char is a literal; a quantifier
+            // after it applies to the last one, as in Perl
+            if let Some(text) = self.parse_quoted() {
+                let mut chars = text.chars();
+                let last = chars.next_back();
+                nodes.extend(chars.map(RegexNode::Literal));
+                if let Some(last) = last {
+                    nodes.push(self.parse_quantifier(RegexNode::Literal(last))?);
+                }
+                continue;
+            }
+            let start = self.pos;
+            nodes.push(self.parse_repeat()?);
+            // Every atom consumes at least one char; if a new construct ever
+            // doesn't, fail instead of looping forever
+            if self.pos == start {
+                return Err(format!("unexpected '{}' at position {}", ch, start));
+            }
+        }
+        Ok(RegexNode::Seq(nodes))
+    }
+
+    // Parse `\Q...\E` at the current position, if it's there, returning the text
+    // between them. Without an `\E` the quote runs to the end of the pattern.
+    fn parse_quoted(&mut self) -> Option<&'a str> {
+        let pattern = self.pattern;
+        let text = pattern[self.pos..].strip_prefix("\\Q")?;
+        let (quoted, len) = match text.find("\\E") {
+            Some(end) => (&text[..end], end + 2),
+            None => (text, text.len()),
+        };
+        self.pos += 2 + len;
+        Some(quoted)
+    }
+
+    // Skip `(?#...)` comments, and in free-spacing mode also whitespace and
+    // `#` comments running to the end of the line
+    fn skip_ignored(&mut self) -> Result<(), String> {
+        loop {
+            let rest = &self.pattern[self.pos..];
+            if rest.starts_with("(?#") {
+                let end = rest.find(')').ok_or(format!("unclosed comment at position {}", self.pos))?;
+                self.pos += end + 1;
+            } else if self.extended && rest.starts_with('#') {
+                self.pos += rest.find('\n').map_or(rest.len(), |end| end + 1);
+            } else if self.extended && rest.starts_with(char::is_whitespace) {
+                self.advance();
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    // Parse a group name up to and including `close`. Names are made of letters,
+    // digits and `_`, and don't start with a digit.
+    fn parse_group_name(&mut self, close: char) -> Result<String, String> {
+        let start = self.pos;
+        let mut name = String::new();
+        while let Some(ch) = self.peek() {
+            if ch == close {
+                break;
+            }
+            name.push(ch);
+            self.advance();
+        }
+        if !self.expect(close) {
+            return Err(format!("unclosed group name at position {}", start));
         }
-        RegexNode::Seq(nodes)
+        let valid = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("invalid group name '{}' at position {}", name, start));
+        }
+        Ok(name)
+    }
+
+    // A backreference to the group called `name`, which must already be open
+    fn named_backreference(&self, name: &str, at: usize) -> Result<RegexNode, String> {
+        match self.names.iter().find(|(n, _)| n == name) {
+            Some(&(_, group_num)) => Ok(RegexNode::Backreference(group_num)),
+            None => Err(format!("unknown group name '{}' at position {}", name, at)),
+        }
+    }
+
+    // Parse repetition: repeat := atom (('?' | '+' | '*' | '{' n (',' m?)? '}') ('?' | '+')?)?
+    fn parse_repeat(&mut self) -> Result<RegexNode, String> {
+        let atom = self.parse_atom()?;
+        self.parse_quantifier(atom)
     }
 
-    // Parse repetition: repeat := atom ('?' | '+' | '*')?
-    fn parse_repeat(&mut self) -> RegexNode {
-        let atom = self.parse_atom();
-        match self.peek() {
+    // Parse the quantifier, if any, that follows an already parsed `atom`
+    fn parse_quantifier(&mut self, atom: RegexNode) -> Result<RegexNode, String> {
+        self.skip_ignored()?;
+        let kind = match self.peek() {
             Some('?') => {
                 self.advance();
-                RegexNode::Repeat {
-                    node: Box::new(atom),
-                    kind: RepeatKind::ZeroOrOne,
-                }
+                RepeatKind::ZeroOrOne
             }
             Some('+') => {
                 self.advance();
-                RegexNode::Repeat {
-                    node: Box::new(atom),
-                    kind: RepeatKind::OneOrMore,
-                }
+                RepeatKind::OneOrMore
             }
             Some('*') => {
                 self.advance();
-                RegexNode::Repeat {
-                    node: Box::new(atom),
-                    kind: RepeatKind::ZeroOrMore,
-                }
-            } 
-            _ => atom,
+                RepeatKind::ZeroOrMore
+            }
+            Some('{') => match self.parse_bounds()? {
+                Some((min, max)) => RepeatKind::Bounded { min, max },
+                // Not a valid quantifier, so the `{` is a literal char (as in grep)
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        // A trailing `?` makes the quantifier lazy, so `??` is an optional atom
+        // that prefers to be skipped. A trailing `+` makes it possessive: `a*+`
+        // is `(?>a*)`.
+        let greedy = !self.expect('?');
+        let possessive = greedy && self.expect('+');
+        let node = RegexNode::Repeat {
+            node: Box::new(atom),
+            kind,
+            greedy,
+        };
+        Ok(if possessive { RegexNode::Atomic(Box::new(node)) } else { node })
+    }
+
+    // Parse `{n}`, `{n,}` or `{n,m}` at the current `{`. If what follows isn't one
+    // of those forms, nothing is consumed and None is returned.
+    fn parse_bounds(&mut self) -> Result<Option<(usize, Option<usize>)>, String> {
+        let open = self.pos;
+        self.advance(); // consume '{'
+        let min = match self.parse_number()? {
+            Some(n) => n,
+            None => {
+                self.pos = open;
+                return Ok(None);
+            }
+        };
+        let max = if self.expect(',') {
+            self.parse_number()?
+        } else {
+            Some(min)
+        };
+        if !self.expect('}') {
+            self.pos = open;
+            return Ok(None);
+        }
+        if let Some(max) = max {
+            if max < min {
+                return Err(format!(
+                    "quantifier {{{},{}}} at position {} has its bounds out of order",
+                    min, max, open
+                ));
+            }
         }
+        Ok(Some((min, max)))
     }
 
-    // Parse atom: atom := '(' alt ')' | '[' '^'? class ']' | '\' esc | '.' | '^' | '$' | literal
-    fn parse_atom(&mut self) -> RegexNode {
-        match self.peek() {
+    // Parse a run of decimal digits, if there is one
+    fn parse_number(&mut self) -> Result<Option<usize>, String> {
+        let start = self.pos;
+        let mut val: usize = 0;
+        while let Some(d) = self.peek().and_then(|ch| ch.to_digit(10)) {
+            self.advance();
+            val = val
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(d as usize))
+                .ok_or(format!("repetition count at position {} is too large", start))?;
+        }
+        Ok(if self.pos > start { Some(val) } else { None })
+    }
+
+    // Parse atom: atom := '(' ('?:' | '?' flags ':' | '?<' name '>' | '?=' | '?!' | '?<=' | '?<!' | '?>')? alt ')' | '(?P=' name ')' | '[' '^'? class ']' | '\' esc | '.' | '^' | '$' | literal
+    fn parse_atom(&mut self) -> Result<RegexNode, String> {
+        let node = match self.peek() {
             // Parenthesized group
             Some('(') => {
+                let open = self.pos;
+                // `(?i:...)` groups without capturing, with the flags applied inside
+                if let Some(flags) = self.parse_flags(':')? {
+                    let outer = self.extended;
+                    self.extended = flags.extended.unwrap_or(outer);
+                    let node = self.parse_alt();
+                    self.extended = outer;
+                    let node = node?;
+                    if !self.expect(')') {
+                        return Err(format!("unclosed '(' at position {}", open));
+                    }
+                    return Ok(RegexNode::Scoped {
+                        node: Box::new(node),
+                        flags,
+                    });
+                }
+                if self.parse_flags(')')?.is_some() {
+                    return Err(format!(
+                        "inline flags at position {} must come at the start of the pattern (use a group like '(?i:...)' elsewhere)",
+                        open
+                    ));
+                }
                 self.advance();
+                // `(?:...)` only groups: it gets no number and captures nothing
+                if self.pattern[self.pos..].starts_with("?:") {
+                    self.pos += 2;
+                    let node = self.parse_alt()?;
+                    if !self.expect(')') {
+                        return Err(format!("unclosed '(' at position {}", open));
+                    }
+                    return Ok(node);
+                }
+                // `(?=...)` and `(?!...)` look ahead without consuming anything,
+                // `(?<=...)` and `(?<!...)` look behind
+                let rest = &self.pattern[self.pos..];
+                let look = ["?=", "?!", "?<=", "?<!"].into_iter().find(|prefix| rest.starts_with(prefix));
+                if let Some(prefix) = look {
+                    self.pos += prefix.len();
+                    let node = Box::new(self.parse_alt()?);
+                    if !self.expect(')') {
+                        return Err(format!("unclosed '(' at position {}", open));
+                    }
+                    let negated = prefix.ends_with('!');
+                    return Ok(if prefix.starts_with("?<") {
+                        RegexNode::Lookbehind { node, negated }
+                    } else {
+                        RegexNode::Lookahead { node, negated }
+                    });
+                }
+                // `(?>...)` groups atomically, without capturing
+                if self.pattern[self.pos..].starts_with("?>") {
+                    self.pos += 2;
+                    let node = self.parse_alt()?;
+                    if !self.expect(')') {
+                        return Err(format!("unclosed '(' at position {}", open));
+                    }
+                    return Ok(RegexNode::Atomic(Box::new(node)));
+                }
+                // `(?P=name)` is a backreference to a named group
+                if self.pattern[self.pos..].starts_with("?P=") {
+                    self.pos += 3;
+                    let name = self.parse_group_name(')')?;
+                    return self.named_backreference(&name, open);
+                }
+                // `(?<name>...)` and `(?P<name>...)` capture like `(...)` and can also
+                // be referred to by name
+                let name = if self.pattern[self.pos..].starts_with("?P<") || self.pattern[self.pos..].starts_with("?<") {
+                    self.pos += if self.pattern[self.pos..].starts_with("?P") { 3 } else { 2 };
+                    Some(self.parse_group_name('>')?)
+                } else {
+                    None
+                };
                 self.ref_count += 1;
                 let group_num = self.ref_count;
-                let node = self.parse_alt();
-                let _ = self.expect(')');
+                if let Some(name) = name {
+                    if self.names.iter().any(|(n, _)| *n == name) {
+                        return Err(format!("duplicate group name '{}' at position {}", name, open));
+                    }
+                    self.names.push((name, group_num));
+                }
+                let node = self.parse_alt()?;
+                if !self.expect(')') {
+                    return Err(format!("unclosed '(' at position {}", open));
+                }
                 RegexNode::Group {
                     group_num,
                     node: Box::new(node),
                 }
             }
+            // A quantifier with no atom before it (`*a`, `(+a)`, `a|?b`, `a**`)
+            Some(c @ ('?' | '+' | '*')) => {
+                return Err(format!(
+                    "quantifier '{}' at position {} has nothing to repeat",
+                    c, self.pos
+                ));
+            }
             // Character class
-            Some('[') => self.parse_char_class(),
+            Some('[') => self.parse_char_class()?,
             // Escape sequences
             Some('\\') => {
                 self.advance();
                 match self.advance() {
-                    Some('d') => RegexNode::Digit,
-                    Some('w') => RegexNode::Word,
+                    Some('d') => RegexNode::Digit { negated: false },
+                    Some('D') => RegexNode::Digit { negated: true },
+                    Some('w') => RegexNode::Word { negated: false },
+                    Some('W') => RegexNode::Word { negated: true },
+                    Some('s') => RegexNode::Space { negated: false },
+                    Some('S') => RegexNode::Space { negated: true },
+                    Some('h') => RegexNode::HorizontalSpace { negated: false },
+                    Some('H') => RegexNode::HorizontalSpace { negated: true },
+                    Some('v') => RegexNode::VerticalSpace { negated: false },
+                    Some('V') => RegexNode::VerticalSpace { negated: true },
+                    Some('<') => RegexNode::WordStart,
+                    Some('>') => RegexNode::WordEnd,
+                    Some('b') => RegexNode::WordBoundary { negated: false },
+                    Some('B') => RegexNode::WordBoundary { negated: true },
+                    Some('A') => RegexNode::InputStart,
+                    Some('z') => RegexNode::InputEnd { before_newline: false },
+                    Some('Z') => RegexNode::InputEnd { before_newline: true },
+                    Some('X') => RegexNode::Grapheme,
+                    // \k<name>: a backreference to a named group
+                    Some('k') if self.peek() == Some('<') => {
+                        let start = self.pos - 2;
+                        self.advance();
+                        let name = self.parse_group_name('>')?;
+                        self.named_backreference(&name, start)?
+                    }
+                    // \0 starts an octal escape (\0, \012, \0101); bare \1-\9 stay backreferences
+                    Some('0') => RegexNode::Literal(self.parse_octal_escape()?),
+                    Some(c @ ('x' | 'u')) => RegexNode::Literal(self.parse_hex_escape(c)?),
+                    Some(c @ ('p' | 'P')) => RegexNode::CharClass {
+                        items: vec![self.parse_unicode_property(c)?],
+                        negated: false,
+                    },
+                    Some(c @ ('t' | 'n' | 'r' | 'f')) => RegexNode::Literal(control_escape(c)),
                     // if digit, then backreference
-                    Some(c) if c.is_digit(10) => {
+                    Some(c) if c.is_ascii_digit() => {
+                        let start = self.pos - 2;
                         // advance till you find non-digit
-                        let mut val: usize = c.to_digit(10).unwrap() as usize;
-                        while let Some(d) = self.peek().and_then(|ch| ch.to_digit(10)) {
+                        let mut digits = c.to_string();
+                        while let Some(d) = self.peek().filter(|ch| ch.is_ascii_digit()) {
                             self.advance();
-                            val = val * 10 + d as usize;
+                            digits.push(d);
                         }
-
-                        if val > self.ref_count || val == 0 {
-                            // Invalid backreference, treat as literal
-                            RegexNode::Literal('\\')
-                        } else {
-                            RegexNode::Backreference(val)
+                        // Only groups opened before the backreference count, as with
+                        // `\k<name>`: a forward reference like `\1(a)` could never have
+                        // anything to match, so it is an error rather than an empty match
+                        match digits.parse::<usize>() {
+                            Ok(val) if val <= self.ref_count => RegexNode::Backreference(val),
+                            _ => {
+                                return Err(format!(
+                                    "backreference '\\{}' at position {} refers to no group opened before it",
+                                    digits, start
+                                ))
+                            }
                         }
                     }
                     Some(c) => RegexNode::Literal(c),
@@ -191,18 +699,107 @@ impl<'a> Parser<'a> {
                 self.advance();
                 RegexNode::EndAnchor
             }
-            // Literal character
+            // Literal character (including a ']' outside of a class)
             Some(c) => {
                 self.advance();
                 RegexNode::Literal(c)
             }
             // End of pattern
             None => RegexNode::Seq(vec![]),
+        };
+        Ok(node)
+    }
+
+    // Parse up to three octal digits following an already consumed `\0`
+    fn parse_octal_escape(&mut self) -> Result<char, String> {
+        let start = self.pos;
+        let mut val: u32 = 0;
+        for _ in 0..3 {
+            match self.peek().and_then(|ch| ch.to_digit(8)) {
+                Some(d) => {
+                    self.advance();
+                    val = val * 8 + d;
+                }
+                None => break,
+            }
+        }
+        char::from_u32(val).ok_or(format!(
+            "octal escape at position {} is not a valid character",
+            start
+        ))
+    }
+
+    // Parse the code point of a hex escape whose `\x` or `\u` (`kind`) was just
+    // consumed: `\xNN` takes exactly two hex digits, `\uNNNN` four, and either
+    // takes one to six in braces, as in `\x{1b}` or `\u{1F600}`
+    fn parse_hex_escape(&mut self, kind: char) -> Result<char, String> {
+        let start = self.pos - 2;
+        let braced = self.expect('{');
+        let width = match (braced, kind) {
+            (true, _) => 6,
+            (false, 'x') => 2,
+            (false, _) => 4,
+        };
+        let mut digits = String::new();
+        while let Some(ch) = self.peek().filter(|ch| ch.is_ascii_hexdigit() && digits.len() < width) {
+            digits.push(ch);
+            self.advance();
         }
+        let complete = if braced {
+            !digits.is_empty() && self.expect('}')
+        } else {
+            digits.len() == width
+        };
+        if !complete {
+            let expected = if braced {
+                "1 to 6 hex digits and a closing '}'".to_string()
+            } else {
+                format!("{} hex digits, or 1 to 6 in braces", width)
+            };
+            return Err(format!("malformed '\\{}' escape at position {} (expected {})", kind, start, expected));
+        }
+        let val = u32::from_str_radix(&digits, 16).unwrap_or(u32::MAX);
+        char::from_u32(val).ok_or(format!(
+            "escape at position {} is not a valid character (U+{})",
+            start, digits
+        ))
+    }
+
+    // Parse the Unicode property of a `\p` or `\P` (`kind`) escape that was just
+    // consumed: a one-letter category like `\pL`, or a name in braces like
+    // `\p{Lu}` or `\p{Uppercase_Letter}`. `\P` and `\p{^...}` are the complement.
+    fn parse_unicode_property(&mut self, kind: char) -> Result<ClassItem, String> {
+        let start = self.pos - 2;
+        let name = if self.expect('{') {
+            let rest = &self.pattern[self.pos..];
+            let end = rest
+                .find('}')
+                .ok_or(format!("unclosed '\\{}{{' at position {}", kind, start))?;
+            self.pos += end + 1;
+            &rest[..end]
+        } else {
+            let rest = &self.pattern[self.pos..];
+            let len = rest.chars().next().map_or(0, char::len_utf8);
+            self.pos += len;
+            &rest[..len]
+        };
+        let (inverted, name) = match name.strip_prefix('^') {
+            Some(name) => (true, name),
+            None => (false, name),
+        };
+        let ranges = unicode_property(name)
+            .ok_or(format!("unknown Unicode property '{}' at position {}", name, start))?;
+        Ok(ClassItem::Unicode {
+            ranges,
+            negated: (kind == 'P') != inverted,
+        })
     }
 
-    // Parse character class: '[' '^'? class ']'
-    fn parse_char_class(&mut self) -> RegexNode {
+    // Parse character class: '[' '^'? (char | char '-' char)* ']'
+    // A `-` first or last in the class is a literal dash, and a `]` first in it
+    // is a literal bracket.
+    fn parse_char_class(&mut self) -> Result<RegexNode, String> {
+        let open = self.pos;
         let _ = self.advance(); // consume '['
         let negated = if self.peek() == Some('^') {
             self.advance();
@@ -210,17 +807,488 @@ impl<'a> Parser<'a> {
         } else {
             false
         };
-        let mut chars_in_class = Vec::new();
+        // The members since the last `&&` or `--`, and the operation so far
+        // with everything before it
+        let mut items = Vec::new();
+        let mut pending: Option<(SetOp, Vec<ClassItem>)> = None;
+        let first = self.pos;
+        let mut operand_start = self.pos;
         while let Some(ch) = self.peek() {
-            if ch == ']' {
+            // A `]` first in the class (`[]a]`, `[^]a]`) is a literal, as in POSIX
+            if ch == ']' && self.pos > first {
                 break;
             }
-            chars_in_class.push(self.advance().unwrap());
+            let start = self.pos;
+            if let Some(op) = self.class_set_op() {
+                if items.is_empty() {
+                    let op = &self.pattern[start..start + 2];
+                    return Err(format!("'{}' at position {} has nothing on its left", op, start));
+                }
+                self.pos += 2;
+                let left = match pending.take() {
+                    None => std::mem::take(&mut items),
+                    Some((prev, left)) => {
+                        let right = std::mem::take(&mut items);
+                        vec![ClassItem::SetOp { op: prev, left, right }]
+                    }
+                };
+                pending = Some((op, left));
+                operand_start = self.pos;
+                continue;
+            }
+            // Unless it starts a POSIX class like `[:alpha:]`, a `[` opens a nested
+            // class, as in `[[a-z][0-9]_]` or `[a-z&&[^aeiou]]`. One right before
+            // the `]` or `^]` that would start such a class stays a literal, so
+            // `[[]` and `[][]` keep meaning what they did before nesting.
+            let rest = &self.pattern[self.pos..];
+            if ch == '[' && !rest.starts_with("[]") && !rest.starts_with("[^]") {
+                if let Some(item) = self.parse_posix_class()? {
+                    items.push(item);
+                    continue;
+                }
+                match self.parse_char_class()? {
+                    RegexNode::CharClass { items: inner, negated } => {
+                        items.push(ClassItem::Class { items: inner, negated })
+                    }
+                    _ => unreachable!("parse_char_class returns a CharClass"),
+                }
+                continue;
+            }
+            let item = self.parse_class_item(open)?;
+            // A `-` between two chars makes a range; first or last in the class
+            // (or after a set like `\d`) it is a literal dash
+            let at_op = self.class_set_op().is_some();
+            let mut rest = self.pattern[self.pos..].chars();
+            match (&item, rest.next(), rest.next()) {
+                (&ClassItem::Single(lo), Some('-'), Some(next)) if next != ']' && !at_op => {
+                    self.advance(); // consume '-'
+                    let hi = match self.parse_class_item(open)? {
+                        ClassItem::Single(hi) => hi,
+                        _ => {
+                            return Err(format!(
+                                "range at position {} ends in a set, not a char",
+                                start
+                            ))
+                        }
+                    };
+                    if hi < lo {
+                        return Err(format!(
+                            "range '{}-{}' at position {} is out of order",
+                            lo, hi, start
+                        ));
+                    }
+                    items.push(ClassItem::Range(lo, hi));
+                }
+                _ => items.push(item),
+            }
+        }
+        if !self.expect(']') {
+            return Err(format!("unclosed '[' at position {}", open));
+        }
+        if let Some((op, left)) = pending {
+            if items.is_empty() {
+                let op = &self.pattern[operand_start - 2..operand_start];
+                return Err(format!("'{}' at position {} has nothing on its right", op, operand_start - 2));
+            }
+            items = vec![ClassItem::SetOp { op, left, right: items }];
+        }
+        Ok(RegexNode::CharClass { items, negated })
+    }
+
+    // Is a class set operator next? `&&` always is; `--` is unless it's the last
+    // thing in the class, so `[+--]` is still the range from `+` to `-`.
+    fn class_set_op(&self) -> Option<SetOp> {
+        let rest = &self.pattern[self.pos..];
+        if rest.starts_with("&&") {
+            Some(SetOp::Intersection)
+        } else if rest.starts_with("--") && !rest[2..].starts_with(']') {
+            Some(SetOp::Difference)
+        } else {
+            None
+        }
+    }
+
+    // Parse one char of a class, an escape, or a POSIX class. `\d`, `\w`, `\s`,
+    // `\h`, `\v` and their uppercase negations are sets; any other escaped char,
+    // such as `\]`, `\-` or `\\`, stands for itself.
+    fn parse_class_item(&mut self, open: usize) -> Result<ClassItem, String> {
+        let unclosed = || format!("unclosed '[' at position {}", open);
+        if let Some(item) = self.parse_posix_class()? {
+            return Ok(item);
+        }
+        let ch = self.advance().ok_or_else(unclosed)?;
+        if ch != '\\' {
+            return Ok(ClassItem::Single(ch));
+        }
+        let escaped = self.advance().ok_or_else(unclosed)?;
+        match escaped {
+            'x' | 'u' => return Ok(ClassItem::Single(self.parse_hex_escape(escaped)?)),
+            'p' | 'P' => return self.parse_unicode_property(escaped),
+            '0' => return Ok(ClassItem::Single(self.parse_octal_escape()?)),
+            't' | 'n' | 'r' | 'f' => return Ok(ClassItem::Single(control_escape(escaped))),
+            _ => {}
+        }
+        let set = match escaped.to_ascii_lowercase() {
+            'd' => Shorthand::Digit,
+            'w' => Shorthand::Word,
+            's' => Shorthand::Space,
+            'h' => Shorthand::HorizontalSpace,
+            'v' => Shorthand::VerticalSpace,
+            _ => return Ok(ClassItem::Single(escaped)),
+        };
+        Ok(ClassItem::Shorthand { set, negated: escaped.is_ascii_uppercase() })
+    }
+
+    // Parse `[:name:]`, or `[:^name:]` for its complement, at the current `[`.
+    // A `[` that doesn't start that form is left for the caller as a plain char.
+    fn parse_posix_class(&mut self) -> Result<Option<ClassItem>, String> {
+        let start = self.pos;
+        let rest = &self.pattern[self.pos..];
+        if !rest.starts_with("[:") {
+            return Ok(None);
         }
-        let _ = self.expect(']');
-        RegexNode::CharClass {
-            chars: chars_in_class,
-            negated,
+        let end = match rest[2..].find(":]") {
+            Some(end) => end + 2,
+            None => return Ok(None),
+        };
+        let name = &rest[2..end];
+        let (negated, name) = match name.strip_prefix('^') {
+            Some(name) => (true, name),
+            None => (false, name),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_lowercase()) {
+            return Ok(None);
         }
+        let class = PosixClass::from_name(name)
+            .ok_or(format!("unknown POSIX class '[:{}:]' at position {}", name, start))?;
+        self.pos += end + 2;
+        Ok(Some(ClassItem::Posix { class, negated }))
+    }
+}
+
+// The control char named by `\t`, `\n`, `\r` or `\f`. (`\v` is the vertical
+// whitespace set, which includes the vertical tab.)
+fn control_escape(c: char) -> char {
+    match c {
+        't' => '\t',
+        'n' => '\n',
+        'r' => '\r',
+        _ => '\u{0c}',
+    }
+}
+
+// The ranges of the Unicode property called `name`: a general category like
+// `Lu`, a script like `Greek`, or a block written as `InBasicLatin`. The kind
+// can also be spelled out, as in `Script=Greek` or `blk:Basic_Latin`. Names are
+// matched loosely, ignoring case, spaces, `_` and `-`, so `Lu`, `uppercase letter`
+// and `Uppercase_Letter` are the same.
+fn unicode_property(name: &str) -> Option<&'static [(char, char)]> {
+    let (kind, value) = match name.split_once(['=', ':']) {
+        Some((kind, value)) => (Some(loose_name(kind)), loose_name(value)),
+        None => (None, loose_name(name)),
+    };
+    let lookup = |table: &[(&str, &'static [(char, char)])], name: &str| {
+        let i = table.binary_search_by(|(n, _)| (*n).cmp(name)).ok()?;
+        Some(table[i].1)
+    };
+    match kind.as_deref() {
+        None => lookup(GENERAL_CATEGORIES, &value)
+            .or_else(|| lookup(SCRIPTS, &value))
+            .or_else(|| lookup(BLOCKS, value.strip_prefix("in")?)),
+        Some("gc" | "generalcategory") => lookup(GENERAL_CATEGORIES, &value),
+        Some("sc" | "script") => lookup(SCRIPTS, &value),
+        Some("blk" | "block") => lookup(BLOCKS, &value),
+        Some(_) => None,
     }
-}
\ No newline at end of file
+}
+
+fn loose_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace() && *c != '_' && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pattern: &str) -> Result<RegexNode, String> {
+        Parser::new(pattern).parse()
+    }
+
+    // The AST in Debug form, which is easier to compare than to match on
+    fn ast(pattern: &str) -> String {
+        format!("{:?}", parse(pattern).unwrap())
+    }
+
+    #[test]
+    fn octal_escapes() {
+        assert_eq!(ast("\\0"), "Seq([Literal('\\0')])");
+        assert_eq!(ast("\\012"), "Seq([Literal('\\n')])");
+        assert_eq!(ast("\\0101"), "Seq([Literal('A')])");
+        assert_eq!(ast("\\0777"), "Seq([Literal('ǿ')])");
+        // At most three digits follow the `0`
+        assert_eq!(ast("\\04000"), "Seq([Literal('Ā'), Literal('0')])");
+    }
+
+    #[test]
+    fn backreferences_need_an_open_group() {
+        assert_eq!(ast("(a)\\1"), "Seq([Group { group_num: 1, node: Seq([Literal('a')]) }, Backreference(1)])");
+        assert!(parse("(a(b)\\2)").is_ok());
+        assert_eq!(
+            parse("a\\12").err(),
+            Some("backreference '\\12' at position 1 refers to no group opened before it".to_string())
+        );
+        assert!(parse("(a)\\2").is_err());
+        assert_eq!(
+            parse("(a)\\99999999999999999999999").err(),
+            Some("backreference '\\99999999999999999999999' at position 3 refers to no group opened before it".to_string())
+        );
+    }
+
+    #[test]
+    fn forward_references_are_rejected() {
+        assert_eq!(
+            parse("\\1(a)").err(),
+            Some("backreference '\\1' at position 0 refers to no group opened before it".to_string())
+        );
+        assert_eq!(
+            parse("(a)\\2(b)").err(),
+            Some("backreference '\\2' at position 3 refers to no group opened before it".to_string())
+        );
+        assert_eq!(parse("\\k<x>(?<x>a)").err(), Some("unknown group name 'x' at position 0".to_string()));
+        // A group is open from its `(`, so it can refer to itself
+        assert!(parse("(a\\1)").is_ok());
+        assert!(parse("(?<x>a\\k<x>)").is_ok());
+    }
+
+    #[test]
+    fn unicode_flag() {
+        assert_eq!(
+            ast("(?-u)a"),
+            "Scoped { node: Seq([Literal('a')]), flags: Flags { case_insensitive: None, multi_line: None, dot_all: None, unicode: Some(false), extended: None } }"
+        );
+        assert!(parse("(?iu:a)").is_ok());
+        assert!(parse("(?-q)a").is_err());
+    }
+
+    // Every char of `text` that the one-char pattern matches, in order
+    fn class_matches(pattern: &str, text: &str) -> String {
+        let regex = crate::Regex::new(pattern).unwrap();
+        text.chars().filter(|c| regex.is_match(&c.to_string()).unwrap()).collect()
+    }
+
+    #[test]
+    fn nested_classes() {
+        assert_eq!(class_matches("[[a-c][0-1]_]", "abcd01 2_"), "abc01_");
+        assert_eq!(class_matches("[[^a-z]x]", "ax1"), "x1");
+        assert_eq!(class_matches("[a-z&&[^aeiou]]", "abe"), "b");
+        assert_eq!(class_matches("[[a-c]--[b]]", "abc"), "ac");
+        assert_eq!(class_matches("[[:digit:][x]]", "1xy"), "1x");
+        assert!(parse("[[a]").is_err());
+        assert!(parse("[a[b]").is_err());
+    }
+
+    #[test]
+    fn brackets_that_cannot_open_a_class_stay_literal() {
+        assert_eq!(class_matches("[[]", "a[]^"), "[");
+        assert_eq!(class_matches("[][]", "a[]^"), "[]");
+        assert_eq!(class_matches("[a[]", "a[]^"), "a[");
+        assert_eq!(class_matches("[[^]", "a[]^"), "[^");
+        assert_eq!(class_matches("[]a]", "a[]^"), "a]");
+        assert_eq!(class_matches("[\\[]", "a[]^"), "[");
+        // The class is `[[]`, then a literal `]`
+        assert_eq!(ast("[[]]"), "Seq([CharClass { items: [Single('[')], negated: false }, Literal(']')])");
+    }
+
+    #[test]
+    fn quantifiers_need_something_to_repeat() {
+        for (pattern, c, pos) in [("*a", '*', 0), ("+a", '+', 0), ("?a", '?', 0), ("a**", '*', 2), ("(+a)", '+', 1), ("a|?b", '?', 2)] {
+            assert_eq!(
+                parse(pattern).err(),
+                Some(format!("quantifier '{}' at position {} has nothing to repeat", c, pos)),
+                "{}",
+                pattern
+            );
+        }
+        // Lazy and possessive suffixes are part of the quantifier, not a second one
+        for pattern in ["a*?", "a+?", "a??", "a*+", "a{2}?"] {
+            assert!(parse(pattern).is_ok(), "{}", pattern);
+        }
+        // Escaped, or inside a class, they are plain chars
+        assert_eq!(class_matches("\\*", "a*"), "*");
+        assert_eq!(class_matches("[*+?]", "a*+?"), "*+?");
+    }
+
+    #[test]
+    fn negated_posix_classes() {
+        assert_eq!(class_matches("[[:^digit:]]", "a1 "), "a ");
+        // The class's own `^` applies after the inner one: a double negation
+        assert_eq!(class_matches("[^[:^digit:]]", "a1 "), "1");
+        assert_eq!(class_matches("[^[:digit:]]", "a1 "), "a ");
+        assert_eq!(class_matches("[[:^alpha:]x]", "ax1"), "x1");
+        assert_eq!(class_matches("[^[:^alpha:]x]", "abx1"), "ab");
+        assert!(parse("[[:^foo:]]").is_err());
+    }
+
+    #[test]
+    fn unmatched_closing_brackets() {
+        assert_eq!(parse("a)b").err(), Some("unmatched ')' at position 1".to_string()));
+        assert_eq!(parse(")").err(), Some("unmatched ')' at position 0".to_string()));
+        assert_eq!(parse("(a))").err(), Some("unmatched ')' at position 3".to_string()));
+        assert_eq!(parse("(a").err(), Some("unclosed '(' at position 0".to_string()));
+        // Outside a class, `]` is just a char
+        assert_eq!(ast("]"), "Seq([Literal(']')])");
+        assert_eq!(ast("a]c"), "Seq([Literal('a'), Literal(']'), Literal('c')])");
+        assert_eq!(parse("[a").err(), Some("unclosed '[' at position 0".to_string()));
+    }
+
+    #[test]
+    fn malformed_patterns_fail_instead_of_looping() {
+        // Every prefix of these must come back, Ok or Err; a construct that
+        // consumed nothing would spin in parse_seq without the guard
+        let patterns = [
+            r"(?P<name>a)(?P=name)",
+            r"(?i:a|b)+?",
+            r"[[:alpha:]&&[^x]]",
+            r"\p{Greek}\k<n>",
+            r"a{2,3}{4}",
+            r"(?<=a)(?<!b)(?>c)",
+            r"\x{1F600}é",
+            r"(?#note)\Qa.b\E",
+        ];
+        for pattern in patterns {
+            for (end, _) in pattern.char_indices() {
+                let _ = parse(&pattern[..end]);
+            }
+        }
+        assert_eq!(parse("(?").err(), Some("quantifier '?' at position 1 has nothing to repeat".to_string()));
+        assert_eq!(parse("(*)").err(), Some("quantifier '*' at position 1 has nothing to repeat".to_string()));
+        assert_eq!(parse("(?P<").err(), Some("unclosed group name at position 4".to_string()));
+        assert_eq!(parse(r"\p{").err(), Some(r"unclosed '\p{' at position 0".to_string()));
+        assert_eq!(parse("(?#").err(), Some("unclosed comment at position 0".to_string()));
+        assert!(parse("(|)").is_ok());
+    }
+
+    #[test]
+    fn named_backreferences() {
+        let expected = "Seq([Group { group_num: 1, node: Seq([Literal('a')]) }, Backreference(1)])";
+        assert_eq!(ast("(?<q>a)\\k<q>"), expected);
+        assert_eq!(ast("(?P<q>a)(?P=q)"), expected);
+        assert_eq!(parse("\\k<nope>").err(), Some("unknown group name 'nope' at position 0".to_string()));
+        assert_eq!(parse("(?P=nope)").err(), Some("unknown group name 'nope' at position 0".to_string()));
+        assert_eq!(parse("(?<q>a)\\k<q").err(), Some("unclosed group name at position 10".to_string()));
+    }
+
+    #[test]
+    fn absolute_anchors() {
+        assert_eq!(
+            ast("\\A\\z\\Z$"),
+            "Seq([InputStart, InputEnd { before_newline: false }, InputEnd { before_newline: true }, EndAnchor])"
+        );
+    }
+
+    #[test]
+    fn posix_classes() {
+        assert_eq!(class_matches("[[:alpha:]]", "1aZ_é!"), "aZé");
+        assert_eq!(class_matches("[[:digit:]]", "a1٣2"), "12");
+        assert_eq!(class_matches("[[:space:]]", " \t\nx"), " \t\n");
+        assert_eq!(class_matches("[[:punct:]]", "1a!-_"), "!-_");
+        assert_eq!(class_matches("[[:upper:][:digit:]]", "aB3"), "B3");
+        assert_eq!(class_matches("[^[:alpha:]]", "ab1c"), "1");
+        // Outside brackets it is an ordinary class of the chars `:alph`
+        assert_eq!(class_matches("[:alpha:]", "a:z"), "a:");
+        assert_eq!(parse("[[:bogus:]]").err(), Some("unknown POSIX class '[:bogus:]' at position 1".to_string()));
+    }
+
+    #[test]
+    fn hex_and_unicode_escapes() {
+        assert_eq!(ast("\\x41\\x{1F600}\\u00e9\\u{1F600}"), "Seq([Literal('A'), Literal('😀'), Literal('é'), Literal('😀')])");
+        for pattern in ["\\x4", "\\xZZ"] {
+            assert_eq!(
+                parse(pattern).err(),
+                Some("malformed '\\x' escape at position 0 (expected 2 hex digits, or 1 to 6 in braces)".to_string())
+            );
+        }
+        assert_eq!(
+            parse("\\x{41").err(),
+            Some("malformed '\\x' escape at position 0 (expected 1 to 6 hex digits and a closing '}')".to_string())
+        );
+        assert_eq!(
+            parse("\\u{}").err(),
+            Some("malformed '\\u' escape at position 0 (expected 1 to 6 hex digits and a closing '}')".to_string())
+        );
+        assert_eq!(
+            parse("\\x{110000}").err(),
+            Some("escape at position 0 is not a valid character (U+110000)".to_string())
+        );
+    }
+
+    #[test]
+    fn control_escapes() {
+        assert_eq!(
+            ast("\\t\\n\\r\\f\\v\\0"),
+            "Seq([Literal('\\t'), Literal('\\n'), Literal('\\r'), Literal('\\u{c}'), VerticalSpace { negated: false }, Literal('\\0')])"
+        );
+        assert_eq!(class_matches("[\\t\\n]", "a\t\nb"), "\t\n");
+        assert_eq!(class_matches("[^\\t]", "a\tb"), "ab");
+    }
+
+    #[test]
+    fn inline_flags_only_at_the_start() {
+        assert_eq!(
+            ast("(?im-s)a"),
+            "Scoped { node: Seq([Literal('a')]), flags: Flags { case_insensitive: Some(true), multi_line: Some(true), dot_all: Some(false), unicode: None, extended: None } }"
+        );
+        assert_eq!(
+            parse("a(?i)b").err(),
+            Some("inline flags at position 1 must come at the start of the pattern (use a group like '(?i:...)' elsewhere)".to_string())
+        );
+        assert_eq!(parse("(?i-q)a").err(), Some("unsupported inline flag 'q' at position 0".to_string()));
+    }
+
+    #[test]
+    fn scoped_flag_groups() {
+        assert_eq!(
+            ast("(?i:a)b"),
+            "Seq([Scoped { node: Seq([Literal('a')]), flags: Flags { case_insensitive: Some(true), multi_line: None, dot_all: None, unicode: None, extended: None } }, Literal('b')])"
+        );
+        assert_eq!(parse("(?i:a").err(), Some("unclosed '(' at position 0".to_string()));
+    }
+
+    #[test]
+    fn free_spacing_and_comments() {
+        assert_eq!(ast("(?x) a b # a comment\n c"), ast("(?x)abc"));
+        assert_eq!(ast("a(?#note)b"), ast("ab"));
+        assert_eq!(parse("a(?#unclosed").err(), Some("unclosed comment at position 1".to_string()));
+    }
+
+    #[test]
+    fn quoted_text() {
+        assert_eq!(ast("\\Qa.b\\E"), ast("a\\.b"));
+        // Without `\E` the quote runs to the end of the pattern
+        assert_eq!(ast("\\Qa.b"), ast("a\\.b"));
+        assert_eq!(ast("x\\Q\\E"), ast("x"));
+        assert_eq!(ast("\\Qab\\E*"), "Seq([Literal('a'), Repeat { node: Literal('b'), kind: ZeroOrMore, greedy: true }])");
+        // Only the quoted text is literal; a `)` after `\E` still closes a group
+        assert_eq!(parse("\\Q(\\E)").err(), Some("unmatched ')' at position 5".to_string()));
+    }
+
+    #[test]
+    fn unicode_category_errors() {
+        assert_eq!(parse("\\p{Foo}").err(), Some("unknown Unicode property 'Foo' at position 0".to_string()));
+        assert_eq!(parse("\\p{L").err(), Some("unclosed '\\p{' at position 0".to_string()));
+        assert!(parse("\\p").is_err());
+        assert!(parse("\\pL\\PN\\p{Lu}\\p{Letter}").is_ok());
+    }
+
+    #[test]
+    fn unicode_script_and_block_errors() {
+        assert_eq!(
+            parse("\\p{Script=Nope}").err(),
+            Some("unknown Unicode property 'Script=Nope' at position 0".to_string())
+        );
+        assert!(parse("\\p{Greek}\\p{sc=Greek}\\p{Script=Greek}\\p{InBasicLatin}\\p{Block=Basic Latin}").is_ok());
+    }
+}
@@ -0,0 +1,810 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::analysis::find_catastrophic;
+use crate::input::Input;
+use crate::matcher::{
+    contains_literal, find_with, literal_text, match_at, partial_match, search_with, Groups, MatchError,
+    MatchKind, MatchOptions, MatchResult, MatchState,
+};
+use crate::nfa::Program;
+use crate::parser::{Parser, RegexNode};
+use crate::replace::{expand, parse_template, Template};
+
+// A compiled pattern: parsed once, then matched against any number of inputs
+pub struct Regex {
+    // The source pattern, kept for `as_str` and Debug output
+    pattern: String,
+    // The patterns it was compiled from, one per `new_any` pattern, for
+    // equality and hashing: `pattern` alone can't tell `new_any(["(a)\\1",
+    // "(b)\\1"])` from `new("(a)\\1|(b)\\1")`, whose `\\1`s mean different groups
+    patterns: Vec<String>,
+    ast: RegexNode,
+    // The AST compiled for fast yes/no matching, unless it has backreferences,
+    // lookarounds or atomic groups
+    program: Option<Program>,
+    // With several patterns from `new_any`, each one's own compiled program, for
+    // `matching_patterns`; empty for a single pattern
+    branch_programs: Vec<Option<Program>>,
+    // The pattern's chars when it is a plain literal like `error`, which
+    // `is_match` finds with a substring search instead of the matcher
+    literal: Option<Vec<char>>,
+    // Set when the pattern has a shape prone to catastrophic backtracking
+    warning: Option<String>,
+    group_count: usize,
+    // Named groups and their numbers, from `(?<name>...)`
+    names: Vec<(String, usize)>,
+    match_kind: MatchKind,
+    options: MatchOptions,
+}
+
+// A single match: its span in char offsets into the searched text, and the
+// matched text itself, borrowed from the searched text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'h> {
+    pub start: usize,
+    pub end: usize,
+    text: &'h str,
+}
+
+impl<'h> Match<'h> {
+    // Slice the matched text out of `input` by mapping the char span to bytes
+    fn new(input: &Input<'h>, start: usize, end: usize) -> Match<'h> {
+        let text = &input.as_str()[input.byte_offset(start)..input.byte_offset(end)];
+        Match { start, end, text }
+    }
+
+    // The matched text, without copying it
+    pub fn as_str(&self) -> &'h str {
+        self.text
+    }
+}
+
+// The groups of a single match. Group 0 is the whole match; a group that didn't
+// take part in the match (e.g. the untaken side of `(a)|(b)`) has no span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures<'h> {
+    spans: Vec<Option<Match<'h>>>,
+}
+
+impl<'h> Captures<'h> {
+    // Build from a match span and the groups recorded on its path
+    fn new(input: &Input<'h>, start: usize, end: usize, groups: &Groups, group_count: usize) -> Captures<'h> {
+        let mut spans = vec![Some(Match::new(input, start, end))];
+        for n in 1..=group_count {
+            spans.push(groups.get(&n).map(|&(start, end)| Match::new(input, start, end)));
+        }
+        Captures { spans }
+    }
+
+    // Group `i`, or None if it didn't participate or doesn't exist
+    pub fn get(&self, i: usize) -> Option<Match<'h>> {
+        self.spans.get(i).copied().flatten()
+    }
+
+    // The replacement `template` would give for this match, as in
+    // `replace_all_with`; groups that didn't participate expand to nothing
+    pub fn expand(&self, template: &Template) -> String {
+        let mut out = String::new();
+        self.expand_into(template, &mut out);
+        out
+    }
+
+    // Like `expand`, but appending to `out`
+    fn expand_into(&self, template: &Template, out: &mut String) {
+        expand(&template.pieces, |n| self.get(n).map(|m| m.as_str()), out);
+    }
+}
+
+// The catastrophic shape in `ast`, if the pattern has one and must be run by
+// the backtracking matcher
+fn backtracking_warning(ast: &RegexNode, program: Option<&Program>) -> Option<String> {
+    match program {
+        Some(_) => None,
+        None => find_catastrophic(ast),
+    }
+}
+
+impl Regex {
+    // Parse the pattern, returning the parser's error message if it is malformed
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser::new(pattern);
+        let ast = parser.parse()?;
+        let program = Program::compile(&ast);
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            patterns: vec![pattern.to_string()],
+            warning: backtracking_warning(&ast, program.as_ref()),
+            program,
+            branch_programs: Vec::new(),
+            literal: literal_text(&ast),
+            ast,
+            group_count: parser.group_count(),
+            names: parser.group_names().to_vec(),
+            match_kind: MatchKind::default(),
+            options: MatchOptions::default(),
+        })
+    }
+
+    // Compile several patterns into one regex that matches wherever any of them
+    // does, as for grep's -e and -f. Groups are numbered across the patterns in
+    // order, so in `["(a)\\1", "(b)\\1"]` the second pattern's group is group 2,
+    // but each `\\1` still refers to the group in its own pattern.
+    pub fn new_any<S: AsRef<str>>(patterns: &[S]) -> Result<Regex, String> {
+        let mut branches = Vec::new();
+        let mut group_count = 0;
+        let mut names = Vec::new();
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            let mut parser = Parser::new(pattern);
+            let mut ast = parser
+                .parse()
+                .map_err(|e| format!("{} (in pattern '{}')", e, pattern))?;
+            ast.shift_groups(group_count);
+            // A name used by several patterns refers to the first one's group
+            names.extend(parser.group_names().iter().map(|(name, n)| (name.clone(), n + group_count)));
+            group_count += parser.group_count();
+            branches.push(ast);
+        }
+        let branch_programs = match branches.len() {
+            1 => Vec::new(),
+            _ => branches.iter().map(Program::compile).collect(),
+        };
+        let ast = RegexNode::Alt(branches);
+        let patterns: Vec<String> = patterns.iter().map(|p| p.as_ref().to_string()).collect();
+        let program = Program::compile(&ast);
+        Ok(Regex {
+            pattern: patterns.join("|"),
+            patterns,
+            warning: backtracking_warning(&ast, program.as_ref()),
+            program,
+            branch_programs,
+            literal: None,
+            ast,
+            group_count,
+            names,
+            match_kind: MatchKind::default(),
+            options: MatchOptions::default(),
+        })
+    }
+
+    // Like `new`, but also rejects patterns that `warning` would flag
+    pub fn new_strict(pattern: &str) -> Result<Regex, String> {
+        let regex = Regex::new(pattern)?;
+        match regex.warning {
+            Some(warning) => Err(warning),
+            None => Ok(regex),
+        }
+    }
+
+    // Why the pattern may backtrack catastrophically (e.g. `(a+)+\1`), if it might.
+    // The regex still works; it can just be very slow on some inputs. Patterns
+    // the NFA can run take linear time whatever their shape, so only those that
+    // need backtracking (for a backreference or atomic group) are ever flagged.
+    pub fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
+    }
+
+    // The pattern this regex was compiled from
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // Choose between POSIX (leftmost-longest) and Perl (leftmost-first) spans
+    pub fn match_kind(mut self, kind: MatchKind) -> Regex {
+        self.match_kind = kind;
+        self
+    }
+
+    // Restrict `\w`, `\s` and `.` to ASCII chars instead of any Unicode char
+    pub fn ascii(mut self, ascii: bool) -> Regex {
+        self.options.ascii = ascii;
+        self
+    }
+
+    // Match letters regardless of case, in literals, classes and backreferences
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Regex {
+        self.options.case_insensitive = case_insensitive;
+        self
+    }
+
+    // Let `^` and `$` also match just after and just before each `\n`, not only
+    // at the ends of the input, like `(?m)`
+    pub fn multi_line(mut self, multi_line: bool) -> Regex {
+        self.options.multi_line = multi_line;
+        self
+    }
+
+    // Let `.` match `\n` too, like `(?s)`. Without it `.` matches any char but `\n`
+    pub fn dot_all(mut self, dot_all: bool) -> Regex {
+        self.options.dot_all = dot_all;
+        self
+    }
+
+    // Give up with `MatchError::ComplexityLimit` once a single search takes more
+    // than `limit` matcher steps, instead of backtracking for as long as it takes
+    pub fn step_limit(mut self, limit: usize) -> Regex {
+        self.options.step_limit = Some(limit);
+        self
+    }
+
+    // Does the pattern match anywhere in `text`?
+    pub fn is_match(&self, text: &str) -> Result<bool, MatchError> {
+        let input: Vec<char> = text.chars().collect();
+        self.search(&input)
+    }
+
+    // Like `is_match`, but against text already split up by `Input::new`,
+    // for matching one input against many patterns
+    pub fn is_match_input(&self, input: &Input) -> Result<bool, MatchError> {
+        self.search(input.chars())
+    }
+
+    // Which of the patterns given to `new_any` match somewhere in `text`, by
+    // index, for keeping per-pattern statistics. Each pattern is searched on its
+    // own, so this costs one search per pattern. A regex from `new` has just the
+    // one pattern, index 0.
+    pub fn matching_patterns(&self, text: &str) -> Result<Vec<usize>, MatchError> {
+        let input: Vec<char> = text.chars().collect();
+        let branches = match &self.ast {
+            RegexNode::Alt(branches) if !self.branch_programs.is_empty() => branches,
+            _ => return Ok(if self.search(&input)? { vec![0] } else { Vec::new() }),
+        };
+        let mut matching = Vec::new();
+        for (i, (branch, program)) in branches.iter().zip(&self.branch_programs).enumerate() {
+            if search_with(branch, program.as_ref(), &input, self.options)? {
+                matching.push(i);
+            }
+        }
+        Ok(matching)
+    }
+
+    // Is there a match anywhere in `input`? A literal pattern (the common
+    // `-i word` search) is a substring search on case-folded text. Otherwise the
+    // compiled NFA is used when there is one, rather than compiling it again for
+    // every input.
+    fn search(&self, input: &[char]) -> Result<bool, MatchError> {
+        if let Some(literal) = &self.literal {
+            return contains_literal(input, literal, self.options);
+        }
+        search_with(&self.ast, self.program.as_ref(), input, self.options)
+    }
+
+    // The leftmost match in `input` at or after `from`, as a char span, plus its
+    // captures if `captures` is set
+    fn find_from(&self, input: &[char], from: usize, captures: bool) -> Result<Option<(usize, usize, Groups)>, MatchError> {
+        let mut state = if captures {
+            MatchState::new(&self.ast, self.options)
+        } else {
+            MatchState::spans_only(&self.ast, self.options)
+        };
+        find_with(&self.ast, self.program.as_ref(), input, from, self.match_kind, &mut state)
+    }
+
+    // Find the leftmost match in `text`, choosing its end according to the match kind
+    pub fn find<'h>(&self, text: &'h str) -> Result<Option<Match<'h>>, MatchError> {
+        let input = Input::new(text);
+        let found = self.find_from(input.chars(), 0, false)?;
+        Ok(found.map(|(start, end, _)| Match::new(&input, start, end)))
+    }
+
+    // Find the leftmost match in `text` along with the spans of its groups
+    pub fn captures<'h>(&self, text: &'h str) -> Result<Option<Captures<'h>>, MatchError> {
+        let input = Input::new(text);
+        let found = self.find_from(input.chars(), 0, true)?;
+        Ok(found.map(|(start, end, groups)| Captures::new(&input, start, end, &groups, self.group_count)))
+    }
+
+    // Match anchored at char offset `start` of `input` (no searching forward) and
+    // return the captures, for tokenizers that step through input themselves
+    pub fn captures_at<'h>(&self, input: &Input<'h>, start: usize) -> Result<Option<Captures<'h>>, MatchError> {
+        let found = match_at(&self.ast, input.chars(), start, self.match_kind, self.options)?;
+        Ok(found.map(|(end, groups)| Captures::new(input, start, end, &groups, self.group_count)))
+    }
+
+    // Iterate over every non-overlapping match in `text`, left to right. Only spans
+    // are computed; `Matches::captures` upgrades a single match when needed. After
+    // an empty match the search resumes one char later, as in `replace_all`.
+    // A search that hits the step limit yields its error and ends the iteration.
+    pub fn find_iter<'h>(&self, text: &'h str) -> Matches<'_, 'h> {
+        Matches {
+            regex: self,
+            input: Input::new(text),
+            pos: 0,
+        }
+    }
+
+    // Like `find_iter`, but yields the captures of every match
+    pub fn captures_iter<'h>(&self, text: &'h str) -> CaptureMatches<'_, 'h> {
+        CaptureMatches {
+            regex: self,
+            input: Input::new(text),
+            pos: 0,
+        }
+    }
+
+    // Number of groups, counting the whole match as group 0 (so `(a)(b)` has 3)
+    pub fn captures_len(&self) -> usize {
+        self.group_count + 1
+    }
+
+    // The number of the group called `name` by `(?<name>...)`, for `Captures::get`
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().find(|(n, _)| n == name).map(|&(_, i)| i)
+    }
+
+    // Parse a replacement template against this pattern's groups, once, for
+    // use with `replace_all_with`. See `replace_all` for the syntax.
+    pub fn template(&self, template: &str) -> Result<Template, String> {
+        Ok(Template {
+            pieces: parse_template(template, self.captures_len(), &self.names)?,
+        })
+    }
+
+    // Replace every non-overlapping match in `text` with `template`, where `$n`
+    // or `${n}` expands to group n, `${name}` to a named group, `$$` to a literal
+    // `$`, and `\n`/`\t` to a newline/tab. Errors if the template names a group
+    // the pattern doesn't have, or if a search hits the step limit.
+    pub fn replace_all(&self, text: &str, template: &str) -> Result<String, String> {
+        self.replace_all_with(text, &self.template(template)?)
+            .map_err(|e| e.to_string())
+    }
+
+    // Like `replace_all`, but only the leftmost match is replaced
+    pub fn replace(&self, text: &str, template: &str) -> Result<String, String> {
+        self.replace_n(text, &self.template(template)?, 1)
+            .map_err(|e| e.to_string())
+    }
+
+    // Like `replace_all`, with a template already parsed by `Regex::template`
+    pub fn replace_all_with(&self, text: &str, template: &Template) -> Result<String, MatchError> {
+        self.replace_n(text, template, usize::MAX)
+    }
+
+    // Replace the first `limit` matches, copying the text between and after them unchanged
+    fn replace_n(&self, text: &str, template: &Template, limit: usize) -> Result<String, MatchError> {
+        let input = Input::new(text);
+        let chars = input.chars();
+        let mut out = String::new();
+        let mut pos = 0;
+        let mut replaced = 0;
+        while replaced < limit {
+            let (start, end, groups) = match self.find_from(chars, pos, true)? {
+                Some(found) => found,
+                None => break,
+            };
+            replaced += 1;
+            out.extend(&chars[pos..start]);
+            Captures::new(&input, start, end, &groups, self.group_count).expand_into(template, &mut out);
+            if end > start {
+                pos = end;
+            } else {
+                // Empty match: keep the next char and move past it so we don't loop
+                if start < chars.len() {
+                    out.push(chars[start]);
+                }
+                pos = start + 1;
+            }
+        }
+        if pos <= chars.len() {
+            out.extend(&chars[pos..]);
+        }
+        Ok(out)
+    }
+
+    // Match all of `text` against the pattern, as a streaming validator would:
+    // `Partial` means the input so far is a valid prefix of some full match.
+    pub fn is_partial_match(&self, text: &str) -> Result<MatchResult, MatchError> {
+        let input: Vec<char> = text.chars().collect();
+        partial_match(&self.ast, &input, self.options)
+    }
+}
+
+// Where to resume searching after a match spanning `start..end`
+fn next_pos(start: usize, end: usize) -> usize {
+    if end > start {
+        end
+    } else {
+        start + 1
+    }
+}
+
+// Iterator over the match spans of one text, from `Regex::find_iter`
+pub struct Matches<'r, 'h> {
+    regex: &'r Regex,
+    input: Input<'h>,
+    pos: usize,
+}
+
+impl<'h> Matches<'_, 'h> {
+    // The captures of a match this iterator returned, recomputed on demand
+    pub fn captures(&self, m: Match) -> Result<Option<Captures<'h>>, MatchError> {
+        self.regex.captures_at(&self.input, m.start)
+    }
+}
+
+impl<'h> Iterator for Matches<'_, 'h> {
+    type Item = Result<Match<'h>, MatchError>;
+
+    fn next(&mut self) -> Option<Result<Match<'h>, MatchError>> {
+        let len = self.input.chars().len();
+        if self.pos > len {
+            return None;
+        }
+        match self.regex.find_from(self.input.chars(), self.pos, false) {
+            Ok(found) => {
+                let (start, end, _) = found?;
+                self.pos = next_pos(start, end);
+                Some(Ok(Match::new(&self.input, start, end)))
+            }
+            Err(e) => {
+                self.pos = len + 1;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// Iterator over the captures of every match in one text, from `Regex::captures_iter`
+pub struct CaptureMatches<'r, 'h> {
+    regex: &'r Regex,
+    input: Input<'h>,
+    pos: usize,
+}
+
+impl<'h> Iterator for CaptureMatches<'_, 'h> {
+    type Item = Result<Captures<'h>, MatchError>;
+
+    fn next(&mut self) -> Option<Result<Captures<'h>, MatchError>> {
+        let len = self.input.chars().len();
+        if self.pos > len {
+            return None;
+        }
+        match self.regex.find_from(self.input.chars(), self.pos, true) {
+            Ok(found) => {
+                let (start, end, groups) = found?;
+                self.pos = next_pos(start, end);
+                Some(Ok(Captures::new(&self.input, start, end, &groups, self.regex.group_count)))
+            }
+            Err(e) => {
+                self.pos = len + 1;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// Two regexes are equal when compiled from the same patterns with the same flags,
+// so a `Regex` can serve as a cache key
+impl PartialEq for Regex {
+    fn eq(&self, other: &Regex) -> bool {
+        self.patterns == other.patterns && self.match_kind == other.match_kind && self.options == other.options
+    }
+}
+
+impl Eq for Regex {}
+
+impl Hash for Regex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.patterns.hash(state);
+        self.match_kind.hash(state);
+        self.options.hash(state);
+    }
+}
+
+// Show the pattern and flags rather than the whole AST
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Regex")
+            .field("pattern", &self.pattern)
+            .field("match_kind", &self.match_kind)
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+
+    use super::*;
+
+    // The text of every group of the leftmost match, group 0 first, or None if
+    // there is no match
+    fn groups(pattern: &str, text: &str) -> Option<Vec<Option<String>>> {
+        let regex = Regex::new(pattern).unwrap();
+        let caps = regex.captures(text).unwrap()?;
+        Some((0..regex.captures_len()).map(|i| caps.get(i).map(|m| m.as_str().to_string())).collect())
+    }
+
+    fn some(groups: &[Option<&str>]) -> Option<Vec<Option<String>>> {
+        Some(groups.iter().map(|g| g.map(String::from)).collect())
+    }
+
+    fn hash(regex: &Regex) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        regex.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn literal_patterns_agree_with_the_matcher() {
+        use crate::matcher::search;
+        let texts = ["", "Error", "ERROR: disk", "an error", "err", "KELVIN \u{212A}", "ΣΊΣΥΦΟΣ σίσυφος", "straße STRASSE"];
+        for pattern in ["error", "ERR", "k", "σίσυφος", "ß", "ss", "", "é"] {
+            let ast = Parser::new(pattern).parse().unwrap();
+            for case_insensitive in [false, true] {
+                let regex = Regex::new(pattern).unwrap().case_insensitive(case_insensitive);
+                assert!(regex.literal.is_some(), "{}", pattern);
+                let options = MatchOptions { case_insensitive, ..MatchOptions::default() };
+                for text in texts {
+                    let input: Vec<char> = text.chars().collect();
+                    assert_eq!(regex.is_match(text), search(&ast, &input, options), "{} in {:?}", pattern, text);
+                }
+            }
+        }
+        assert!(Regex::new("err.r").unwrap().literal.is_none());
+        assert!(Regex::new("(?i)error").unwrap().literal.is_none());
+        assert_eq!(Regex::new("error").unwrap().case_insensitive(true).is_match("An ERROR"), Ok(true));
+        assert_eq!(Regex::new("\\u212Aelvin").unwrap().case_insensitive(true).is_match("kELVIN"), Ok(true));
+    }
+
+    #[test]
+    fn same_pattern_and_flags_are_equal() {
+        let a = Regex::new("a+b").unwrap().case_insensitive(true);
+        let b = Regex::new("a+b").unwrap().case_insensitive(true);
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_ne!(a, Regex::new("a+b").unwrap());
+        assert_ne!(Regex::new("a+b").unwrap(), Regex::new("a+c").unwrap());
+        assert_ne!(
+            Regex::new("a+b").unwrap(),
+            Regex::new("a+b").unwrap().match_kind(MatchKind::LeftmostLongest)
+        );
+    }
+
+    #[test]
+    fn combined_patterns_differ_from_their_join() {
+        let any = Regex::new_any(&["(a)\\1", "(b)\\1"]).unwrap();
+        let joined = Regex::new("(a)\\1|(b)\\1").unwrap();
+        assert_eq!(any.as_str(), joined.as_str());
+        // In the joined pattern the second `\1` refers to the unset `(a)`
+        assert_eq!(any.is_match("b"), Ok(false));
+        assert_eq!(joined.is_match("b"), Ok(true));
+        assert_ne!(any, joined);
+        assert_ne!(hash(&any), hash(&joined));
+        assert_eq!(any, Regex::new_any(&["(a)\\1", "(b)\\1"]).unwrap());
+    }
+
+    #[test]
+    fn matching_patterns_names_each_pattern_that_matches() {
+        let any = Regex::new_any(&["err(or)?", "(b)\\1", "^\\d+", "x|y"]).unwrap();
+        assert_eq!(any.matching_patterns("42 errors"), Ok(vec![0, 2]));
+        assert_eq!(any.matching_patterns("bb or y"), Ok(vec![1, 3]));
+        assert_eq!(any.matching_patterns("none"), Ok(vec![]));
+        let any = any.case_insensitive(true).step_limit(1);
+        assert_eq!(any.matching_patterns("ERR"), Err(MatchError::ComplexityLimit));
+        // A single pattern's own alternatives are all pattern 0
+        let one = Regex::new("x|y").unwrap();
+        assert_eq!(one.matching_patterns("y"), Ok(vec![0]));
+        assert_eq!(Regex::new_any(&["x|y"]).unwrap().matching_patterns("z"), Ok(vec![]));
+    }
+
+    #[test]
+    fn debug_shows_the_pattern_and_flags() {
+        let debug = format!("{:?}", Regex::new("a+b").unwrap());
+        assert!(debug.starts_with("Regex { pattern: \"a+b\", match_kind: LeftmostFirst"), "{}", debug);
+    }
+
+    #[test]
+    fn only_the_taken_branch_captures() {
+        assert_eq!(groups("(a)|(b)", "b"), some(&[Some("b"), None, Some("b")]));
+        assert_eq!(groups("(a)|(b)", "a"), some(&[Some("a"), Some("a"), None]));
+        // A branch that captured and then failed leaves nothing behind
+        assert_eq!(groups("(a)x|(a)y", "ay"), some(&[Some("ay"), None, Some("a")]));
+        assert_eq!(groups("(?:(a)|b)+", "ab"), some(&[Some("ab"), Some("a")]));
+    }
+
+    #[test]
+    fn group_zero_is_the_whole_match() {
+        assert_eq!(groups("(abc)", "xabcx"), some(&[Some("abc"), Some("abc")]));
+        assert_eq!(groups("a(b)c", "abc"), some(&[Some("abc"), Some("b")]));
+        assert_eq!(groups("b+", "abbc"), some(&[Some("bb")]));
+        assert_eq!(groups("x*", "abc"), some(&[Some("")]));
+        assert_eq!(groups("z", "abc"), None);
+        let regex = Regex::new("b+").unwrap();
+        assert_eq!(regex.captures_len(), 1);
+        let caps = regex.captures("abbc").unwrap().unwrap();
+        assert_eq!((caps.get(0).unwrap().start, caps.get(0).unwrap().end), (1, 3));
+        assert_eq!(caps.get(1), None);
+    }
+
+    #[test]
+    fn backreferences_read_the_groups_of_their_own_path() {
+        assert_eq!(groups("(a)\\1", "aa"), some(&[Some("aa"), Some("a")]));
+        assert_eq!(groups("(a)\\1", "ax"), None);
+        // `x` matches by the other branch, without group 1
+        assert_eq!(groups("(a)\\1|x", "ax"), some(&[Some("x"), None]));
+        // Each start of `(a|b)` must keep its own capture for `\1` to compare against
+        assert_eq!(groups("(a|b)*\\1", "abb"), some(&[Some("abb"), Some("b")]));
+        assert_eq!(groups("(a|ab)(c|bcd)\\2", "abcdbcd"), some(&[Some("abcdbcd"), Some("a"), Some("bcd")]));
+    }
+
+    #[test]
+    fn a_backreference_to_an_unset_group_matches_nothing() {
+        let regex = Regex::new("(a)?\\1b").unwrap();
+        assert_eq!(regex.is_match("b"), Ok(true));
+        assert_eq!(groups("(a)?\\1b", "b"), some(&[Some("b"), None]));
+        assert_eq!(groups("(a)?\\1b", "aab"), some(&[Some("aab"), Some("a")]));
+        // With the group set, `\1` must match its text again
+        assert_eq!(groups("(a)?\\1b", "ab"), some(&[Some("b"), None]));
+        let found: Vec<&str> = regex.find_iter("b ab aab").map(|m| m.unwrap().as_str()).collect();
+        assert_eq!(found, ["b", "b", "aab"]);
+    }
+
+    #[test]
+    fn a_skipped_optional_group_does_not_participate() {
+        assert_eq!(groups("(abc)?d", "abcd"), some(&[Some("abcd"), Some("abc")]));
+        assert_eq!(groups("(abc)?d", "d"), some(&[Some("d"), None]));
+        assert_eq!(groups("(abc)?d", "abd"), some(&[Some("d"), None]));
+        assert_eq!(groups("(a)??a", "a"), some(&[Some("a"), None]));
+    }
+
+    #[test]
+    fn nested_groups_are_numbered_by_their_open_paren() {
+        assert_eq!(groups("((a)(b))", "ab"), some(&[Some("ab"), Some("ab"), Some("a"), Some("b")]));
+        assert_eq!(Regex::new("((a)(b))").unwrap().replace_all("ab", "$1-$2-$3"), Ok("ab-a-b".to_string()));
+        assert_eq!(groups("(a(?:b)(c(d)))", "abcd"), some(&[Some("abcd"), Some("abcd"), Some("cd"), Some("d")]));
+        // The inner group of a branch not taken is unset, and numbering still counts it
+        assert_eq!(groups("((x)|(y))(z)", "yz"), some(&[Some("yz"), Some("y"), None, Some("y"), Some("z")]));
+        assert_eq!(Regex::new("((x)|(y))").unwrap().replace_all("y", "[$2|$3]"), Ok("[|y]".to_string()));
+    }
+
+    #[test]
+    fn templates_reach_every_level_of_nested_groups() {
+        // Three levels deep, plus an optional group that doesn't take part on the first match
+        let regex = Regex::new("(((a)b)c)(d)?").unwrap();
+        assert_eq!(
+            regex.replace_all("abc abcd", "<$0|$1|$2|$3|$4>"),
+            Ok("<abc|abc|ab|a|> <abcd|abc|ab|a|d>".to_string())
+        );
+        // A repeated group keeps an inner group from an earlier iteration, as Perl does
+        assert_eq!(Regex::new("((a)|(b))+").unwrap().replace_all("ab", "<$1|$2|$3>"), Ok("<b|a|b>".to_string()));
+    }
+
+    #[test]
+    fn find_iter_steps_past_empty_matches() {
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            Regex::new(pattern).unwrap().find_iter(text).map(|m| m.map(|m| (m.start, m.end)).unwrap()).collect()
+        };
+        assert_eq!(spans("a*", "baac"), [(0, 0), (1, 3), (3, 3), (4, 4)]);
+        assert_eq!(spans("^", "ab"), [(0, 0)]);
+        assert_eq!(spans("\\b", "ab cd"), [(0, 0), (2, 2), (3, 3), (5, 5)]);
+        assert_eq!(spans("x*", ""), [(0, 0)]);
+    }
+
+    #[test]
+    fn matches_borrow_from_multibyte_text() {
+        let regex = Regex::new("h(é+)(l+)").unwrap();
+        let text = String::from("xhéllo");
+        let caps = regex.captures(&text).unwrap().unwrap();
+        let whole = caps.get(0).unwrap();
+        // Offsets count chars, slices come straight from `text`
+        assert_eq!((whole.start, whole.end), (1, 5));
+        assert_eq!(whole.as_str(), "héll");
+        assert_eq!(caps.get(1).unwrap().as_str(), "é");
+        assert_eq!(caps.get(2).unwrap().as_str(), "ll");
+        assert!(std::ptr::eq(caps.get(2).unwrap().as_str().as_ptr(), text[4..].as_ptr()));
+        let words: Vec<&str> = Regex::new("\\w+").unwrap().find_iter("ça va, été").map(|m| m.unwrap().as_str()).collect();
+        assert_eq!(words, ["ça", "va", "été"]);
+    }
+
+    #[test]
+    fn going_over_the_step_limit_is_an_error() {
+        // Backreferences keep `(a|aa)*` off the NFA and the memo
+        let regex = Regex::new("(a|aa)*\\1c").unwrap().step_limit(10_000);
+        let text = "a".repeat(40);
+        assert_eq!(regex.is_match(&text), Err(MatchError::ComplexityLimit));
+        assert_eq!(regex.find(&text), Err(MatchError::ComplexityLimit));
+        assert!(regex.captures(&text).is_err());
+        assert!(regex.find_iter(&text).next().unwrap().is_err());
+        assert!(regex.replace_all(&text, "x").is_err());
+        // The same search is fine within a bigger budget, or on a short input
+        assert_eq!(regex.is_match("aac"), Ok(true));
+        assert_eq!(Regex::new("(a|aa)*\\1c").unwrap().is_match("aaaaac"), Ok(true));
+        // Searches the NFA runs count their steps too
+        let nfa = Regex::new("(a*)*b").unwrap().step_limit(100);
+        assert_eq!(nfa.is_match(&text), Err(MatchError::ComplexityLimit));
+        assert_eq!(Regex::new("(a*)*b").unwrap().is_match(&text), Ok(false));
+        // So do literal patterns, which skip both for a substring search
+        let literal = |limit: usize| Regex::new("abc").unwrap().step_limit(limit);
+        assert_eq!(literal(1).is_match("abc"), Err(MatchError::ComplexityLimit));
+        assert_eq!(literal(3).is_match("abc"), Ok(true));
+        // One step at each of the two mismatched starts, then three for the match
+        assert_eq!(literal(4).case_insensitive(true).is_match("xxABC"), Err(MatchError::ComplexityLimit));
+        assert_eq!(literal(5).case_insensitive(true).is_match("xxABC"), Ok(true));
+    }
+
+    #[test]
+    fn partial_matches_tell_prefixes_from_dead_ends() {
+        let partial = |pattern: &str, text: &str| Regex::new(pattern).unwrap().is_partial_match(text).unwrap();
+        // A prefix that more input could still complete
+        assert_eq!(partial("\\d{3}-\\d{4}", "555-12"), MatchResult::Partial);
+        assert_eq!(partial("a+b", "aaa"), MatchResult::Partial);
+        // Input that can never match, however it goes on
+        assert_eq!(partial("\\d{3}-\\d{4}", "55a"), MatchResult::NoMatch);
+        assert_eq!(partial("abc", "abcd"), MatchResult::NoMatch);
+        assert_eq!(partial("(ab)+", "abab"), MatchResult::Full);
+        // The empty string is a prefix of everything, and a full match of `a*`
+        assert_eq!(partial("abc", ""), MatchResult::Partial);
+        assert_eq!(partial("a*", ""), MatchResult::Full);
+    }
+
+    #[test]
+    fn leftmost_longest_picks_the_longest_match_at_the_leftmost_start() {
+        let texts = |pattern: &str, kind: MatchKind, text: &str| {
+            let regex = Regex::new(pattern).unwrap().match_kind(kind);
+            let caps = regex.captures(text).unwrap().unwrap();
+            (0..regex.captures_len()).map(|i| caps.get(i).map(|m| m.as_str().to_string())).collect::<Vec<_>>()
+        };
+        assert_eq!(texts("a|ab", MatchKind::LeftmostFirst, "ab"), [Some("a".to_string())]);
+        assert_eq!(texts("a|ab", MatchKind::LeftmostLongest, "ab"), [Some("ab".to_string())]);
+        // Only `a` then `bcd` reaches the end; `ab` then `c` stops short
+        for kind in [MatchKind::LeftmostFirst, MatchKind::LeftmostLongest] {
+            assert_eq!(texts("(a|ab)(c|bcd)", kind, "abcd"), some(&[Some("abcd"), Some("a"), Some("bcd")]).unwrap());
+        }
+        assert_eq!(texts("a*?", MatchKind::LeftmostLongest, "aaa"), [Some("aaa".to_string())]);
+        // A later start is never preferred, however long its match
+        let regex = Regex::new("b|abc|bcdef").unwrap().match_kind(MatchKind::LeftmostLongest);
+        assert_eq!(regex.find("xabcdef").unwrap().map(|m| m.as_str()), Some("abc"));
+    }
+
+    #[test]
+    fn captures_at_matches_only_at_the_given_offset() {
+        let input = Input::new("foo bar\nbaz");
+        let at = |pattern: &str, start: usize| {
+            let caps = Regex::new(pattern).unwrap().captures_at(&input, start).unwrap()?;
+            Some((caps.get(0).unwrap().start, caps.get(0).unwrap().as_str(), caps.get(1).map(|m| m.as_str())))
+        };
+        assert_eq!(at("(\\w)\\w+", 4), Some((4, "bar", Some("b"))));
+        assert_eq!(at("\\w+", 5), Some((5, "ar", None)));
+        // Anchored: no searching forward for a later match
+        assert_eq!(at("bar", 3), None);
+        // `^` still means the start of the input, not the offset
+        assert_eq!(at("^\\w+", 4), None);
+        assert_eq!(at("^foo", 0), Some((0, "foo", None)));
+        // Word boundaries and lookbehind see the text before the offset
+        assert_eq!(at("\\b\\w+", 4), Some((4, "bar", None)));
+        assert_eq!(at("\\b\\w+", 5), None);
+        assert_eq!(at("\\Bar", 5), Some((5, "ar", None)));
+        assert_eq!(at("(?<=o )b", 4), Some((4, "b", None)));
+    }
+
+    #[test]
+    fn one_input_against_many_patterns() {
+        let text = "naïve café, 42 crêpes";
+        let input = Input::new(text);
+        assert_eq!(input.as_str(), text);
+        assert_eq!(input.chars().len(), text.chars().count());
+        // Char 3 is the `v` after the two-byte `ï`; the end maps to the text's length
+        assert_eq!(input.byte_offset(3), 4);
+        assert_eq!(input.byte_offset(input.chars().len()), text.len());
+        for (pattern, expected) in [("café", true), ("CAFÉ", false), ("\\d+ cr", true), ("^naïve", true), ("é$", false), ("(e)\\1", false)] {
+            let regex = Regex::new(pattern).unwrap();
+            assert_eq!(regex.is_match_input(&input), Ok(expected), "{}", pattern);
+            assert_eq!(regex.is_match(text), Ok(expected), "{}", pattern);
+        }
+        let regex = Regex::new("CAFÉ").unwrap().case_insensitive(true);
+        assert_eq!(regex.is_match_input(&input), Ok(true));
+        assert_eq!(Regex::new("x").unwrap().is_match_input(&Input::new("")), Ok(false));
+    }
+
+    #[test]
+    fn non_capturing_groups_take_no_number() {
+        assert_eq!(groups("(?:ab)+", "xababy"), some(&[Some("abab")]));
+        assert_eq!(groups("(?:a)(b)\\1", "abb"), some(&[Some("abb"), Some("b")]));
+        assert_eq!(groups("(a(?:b(c))d)\\2", "abcdc"), some(&[Some("abcdc"), Some("abcd"), Some("c")]));
+        assert_eq!(Regex::new("(?:(?:a)(b))(?:c)").unwrap().captures_len(), 2);
+        assert_eq!(Regex::new("(?:ab").err(), Some("unclosed '(' at position 0".to_string()));
+    }
+}
@@ -0,0 +1,209 @@
+// One piece of a parsed replacement template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Piece {
+    Literal(String),
+    // `$n`: the text of group n, where group 0 is the whole match
+    Group(usize),
+}
+
+// A replacement template parsed once against a pattern's group count, for
+// applying to many lines with `Regex::replace_all_with`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pub(crate) pieces: Vec<Piece>,
+}
+
+// Parse a replacement template like `$2-$1`. `$n` and `${n}` name group n (the
+// braces disambiguate `${1}0` from `$10`), `${name}` names a group by its name
+// in `names`, `$$` is a literal dollar sign, and a
+// `$` not followed by a digit or `{` is kept as-is. `\n`, `\t` and `\\` are
+// escapes; any other backslash is kept. Referencing a group the pattern doesn't
+// have is an error rather than a silent empty substitution.
+pub fn parse_template(template: &str, captures_len: usize, names: &[(String, usize)]) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.peek() {
+                Some('n') => literal.push('\n'),
+                Some('t') => literal.push('\t'),
+                Some('\\') => literal.push('\\'),
+                _ => {
+                    literal.push('\\');
+                    continue;
+                }
+            }
+            chars.next();
+            continue;
+        }
+        if ch != '$' {
+            literal.push(ch);
+            continue;
+        }
+        let num = match chars.peek() {
+            Some('$') => {
+                chars.next();
+                literal.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unclosed '${{{}' in replacement", name)),
+                    }
+                }
+                if let Some(&(_, num)) = names.iter().find(|(n, _)| *n == name) {
+                    num
+                } else if name.is_empty() || !name.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(format!("replacement references unknown group '{}'", name));
+                } else {
+                    name.parse::<usize>()
+                        .map_err(|_| format!("replacement group '{}' is too large", name))?
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    chars.next();
+                    digits.push(c);
+                }
+                digits.parse::<usize>()
+                    .map_err(|_| format!("replacement group '{}' is too large", digits))?
+            }
+            _ => {
+                literal.push('$');
+                continue;
+            }
+        };
+        if num >= captures_len {
+            return Err(format!(
+                "replacement references group {} but the pattern only has {} group(s)",
+                num,
+                captures_len - 1
+            ));
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+        }
+        pieces.push(Piece::Group(num));
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+// Expand parsed template pieces for a single match, where `group(n)` is the
+// text of group n (group 0 being the whole match). Groups that didn't
+// participate in the match expand to nothing.
+pub fn expand<'h>(pieces: &[Piece], group: impl Fn(usize) -> Option<&'h str>, out: &mut String) {
+    for piece in pieces {
+        match piece {
+            Piece::Literal(s) => out.push_str(s),
+            Piece::Group(n) => out.push_str(group(*n).unwrap_or("")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Regex;
+
+    #[test]
+    fn expands_groups_by_number() {
+        let regex = Regex::new("(a)(b)").unwrap();
+        assert_eq!(regex.replace_all("xaby", "$2$1"), Ok("xbay".to_string()));
+        assert_eq!(regex.replace_all("ab", "[$0] ${1}0 $$1"), Ok("[ab] a0 $1".to_string()));
+    }
+
+    #[test]
+    fn groups_the_pattern_lacks_are_an_error() {
+        let regex = Regex::new("(a)(b)").unwrap();
+        assert_eq!(regex.captures_len(), 3);
+        assert_eq!(
+            regex.replace_all("ab", "$1$9"),
+            Err("replacement references group 9 but the pattern only has 2 group(s)".to_string())
+        );
+        assert!(regex.replace_all("ab", "${9}").is_err());
+        assert!(regex.replace_all("ab", "${nope}").is_err());
+    }
+
+    #[test]
+    fn groups_that_did_not_take_part_expand_to_nothing() {
+        let regex = Regex::new("(a)|(b)").unwrap();
+        assert_eq!(regex.replace_all("ab", "<$1$2>"), Ok("<a><b>".to_string()));
+    }
+
+    #[test]
+    fn huge_group_numbers_are_an_error() {
+        for template in ["$99999999999999999999999", "${99999999999999999999999}"] {
+            assert_eq!(
+                parse_template(template, 3, &[]),
+                Err("replacement group '99999999999999999999999' is too large".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn escapes_and_stray_dollars() {
+        assert_eq!(
+            parse_template(r"a\n\tb$ $x\q\\", 1, &[]),
+            Ok(vec![Piece::Literal("a\n\tb$ $x\\q\\".to_string())])
+        );
+    }
+
+    #[test]
+    fn braces_end_a_group_number() {
+        let regex = Regex::new("(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)").unwrap();
+        let text = "abcdefghij";
+        assert_eq!(regex.replace_all(text, "${1}0"), Ok("a0".to_string()));
+        assert_eq!(regex.replace_all(text, "$10"), Ok("j".to_string()));
+        assert_eq!(regex.replace_all(text, "$1$0"), Ok("aabcdefghij".to_string()));
+        assert_eq!(regex.replace_all(text, "$$10"), Ok("$10".to_string()));
+    }
+
+    #[test]
+    fn named_groups_and_escapes() {
+        let regex = Regex::new("(?P<key>\\w+)=(?<value>\\w+)").unwrap();
+        assert_eq!(regex.replace_all("a=1 b=2", "${value}:${key}"), Ok("1:a 2:b".to_string()));
+        assert_eq!(regex.replace_all("a=1", "${key}\\t${2}\\n"), Ok("a\t1\n".to_string()));
+        assert_eq!(regex.replace_all("a=1", "$$${key}"), Ok("$a".to_string()));
+        assert!(regex.replace_all("a=1", "${keys}").is_err());
+    }
+
+    #[test]
+    fn whole_match_wraps_every_match() {
+        let regex = Regex::new("(\\w+)").unwrap();
+        assert_eq!(regex.replace_all("hello world", "[$0]"), Ok("[hello] [world]".to_string()));
+        assert_eq!(regex.replace_all("héllo wörld!", "[$0|$1]"), Ok("[héllo|héllo] [wörld|wörld]!".to_string()));
+        assert_eq!(Regex::new("ö").unwrap().replace_all("öö", "${0}o"), Ok("öoöo".to_string()));
+        assert_eq!(regex.replace_all("", "[$0]"), Ok(String::new()));
+    }
+
+    #[test]
+    fn replace_stops_after_the_leftmost_match() {
+        let regex = Regex::new("(\\w+)").unwrap();
+        assert_eq!(regex.replace("hello world", "[$0]"), Ok("[hello] world".to_string()));
+        assert_eq!(regex.replace("¡héllo wörld", "<$1>"), Ok("¡<héllo> wörld".to_string()));
+        assert_eq!(regex.replace("!?", "[$0]"), Ok("!?".to_string()));
+        // A zero-width leftmost match is still the one replaced
+        assert_eq!(Regex::new("x*").unwrap().replace("abc", "-"), Ok("-abc".to_string()));
+        assert!(regex.replace("a", "$2").is_err());
+    }
+
+    #[test]
+    fn captures_expand_one_match() {
+        let regex = Regex::new("(?<key>\\w+)=(\\w+)?").unwrap();
+        let template = regex.template("${key}:[$2] $0").unwrap();
+        let caps = regex.captures("x a=1").unwrap().unwrap();
+        assert_eq!(caps.expand(&template), "a:[1] a=1");
+        let caps = regex.captures("b=").unwrap().unwrap();
+        assert_eq!(caps.expand(&template), "b:[] b=");
+    }
+}
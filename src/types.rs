@@ -0,0 +1,76 @@
+use std::io::{self, Write};
+
+// Built-in file types for -t/-T: a name and the file name globs it covers.
+// A glob is either `*.ext` (any file with that suffix) or an exact file name.
+const FILE_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    ("cpp", &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh", "*.hxx", "*.h"]),
+    ("go", &["*.go"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.mjs", "*.cjs", "*.jsx"]),
+    ("json", &["*.json"]),
+    ("make", &["Makefile", "makefile", "GNUmakefile", "*.mk"]),
+    ("md", &["*.md", "*.markdown"]),
+    ("py", &["*.py", "*.pyi"]),
+    ("rb", &["*.rb"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.sh", "*.bash", "*.zsh"]),
+    ("toml", &["*.toml"]),
+    ("ts", &["*.ts", "*.tsx"]),
+    ("txt", &["*.txt"]),
+    ("web", &["*.html", "*.htm", "*.css", "*.js"]),
+    ("yaml", &["*.yml", "*.yaml"]),
+];
+
+// Is `name` one of the built-in types?
+pub fn is_known(name: &str) -> bool {
+    FILE_TYPES.iter().any(|(n, _)| *n == name)
+}
+
+// Does a file with this name belong to the type `name`?
+pub fn matches(name: &str, file_name: &str) -> bool {
+    FILE_TYPES
+        .iter()
+        .filter(|(n, _)| *n == name)
+        .flat_map(|(_, globs)| globs.iter())
+        .any(|glob| match glob.strip_prefix('*') {
+            Some(suffix) => file_name.ends_with(suffix),
+            None => file_name == *glob,
+        })
+}
+
+// Write every type and its globs, for --type-list
+pub fn write_type_list(out: &mut dyn Write) -> io::Result<()> {
+    for (name, globs) in FILE_TYPES {
+        writeln!(out, "{}: {}", name, globs.join(", "))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A reader that has gone away, as after `| head`
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn type_list_reports_write_errors() {
+        let mut out = Vec::new();
+        write_type_list(&mut out).unwrap();
+        let list = String::from_utf8(out).unwrap();
+        assert!(list.lines().any(|line| line == "rust: *.rs"), "{}", list);
+        assert_eq!(list.lines().count(), FILE_TYPES.len());
+        assert_eq!(write_type_list(&mut ClosedPipe).unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}